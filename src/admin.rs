@@ -0,0 +1,80 @@
+use crate::sse::frame_payload;
+use crate::AppState;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+
+// Admin/debug routes expose internal state, so they are only mounted when
+// `enable_test_endpoints` is set and require `admin_token` when one is configured
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(
+        "/admin/instruments/{name}/buffer",
+        web::get().to(get_instrument_buffer),
+    )
+    .route("/admin/audit", web::get().to(get_audit_log));
+}
+
+// Returns the rejection response when the request lacks the configured admin bearer token
+pub(crate) fn reject_unauthorized(req: &HttpRequest, app_state: &AppState) -> Option<HttpResponse> {
+    let expected = app_state.admin_token.as_deref()?;
+
+    let provided = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    if provided == Some(expected) {
+        None
+    } else {
+        Some(HttpResponse::Unauthorized().json(serde_json::json!({"error": "unauthorized"})))
+    }
+}
+
+// Admin endpoint listing the frames currently held in an instrument's replay buffer
+async fn get_instrument_buffer(
+    req: HttpRequest,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if let Some(rejection) = reject_unauthorized(&req, &app_state) {
+        return Ok(rejection);
+    }
+
+    let instrument = app_state.resolve_instrument(path.as_str());
+    if app_state.instrument_sender(instrument).is_none() {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "instrument_not_found",
+            "instrument": instrument
+        })));
+    }
+
+    let frames: Vec<serde_json::Value> = app_state
+        .replay
+        .last(instrument, app_state.replay.capacity())
+        .iter()
+        .map(|buffered| {
+            serde_json::json!({
+                "seq": buffered.seq,
+                "received_at": buffered.received_at.to_rfc3339(),
+                "payload": frame_payload(&buffered.frame)
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "instrument": instrument,
+        "capacity": app_state.replay.capacity(),
+        "frames": frames
+    })))
+}
+
+// Admin endpoint listing recorded admin actions, oldest first
+async fn get_audit_log(req: HttpRequest, app_state: web::Data<AppState>) -> Result<HttpResponse> {
+    if let Some(rejection) = reject_unauthorized(&req, &app_state) {
+        return Ok(rejection);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "entries": app_state.audit_log.entries()
+    })))
+}
@@ -0,0 +1,120 @@
+use crate::RedisPool;
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+// One admin action: who did what to which instrument, and with which parameters
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub action: String,
+    pub instrument: Option<String>,
+    pub actor: String, // key id from api_key_id, never the raw key
+    pub parameters: serde_json::Value,
+}
+
+// Stable id for a key (FNV-1a), so the log can name an actor without storing the
+// credential itself. Not a cryptographic hash: a guessable key can be matched to its id.
+pub fn api_key_id(key: &str) -> String {
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("key-{:016x}", hash)
+}
+
+// The caller's X-API-Key, else its admin bearer token, as a key id
+pub fn actor(req: &HttpRequest) -> String {
+    let api_key = req.headers().get("X-API-Key").and_then(|v| v.to_str().ok());
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match api_key.or(bearer) {
+        Some(key) => api_key_id(key),
+        None => "anonymous".to_string(),
+    }
+}
+
+// Bounded in-memory trail of admin actions, newest last, optionally mirrored to a Redis
+// list (LPUSH, trimmed to the same size) so it outlives restarts
+pub struct AuditLog {
+    capacity: usize,
+    entries: Mutex<VecDeque<AuditEntry>>,
+    persist: Option<(RedisPool, String)>, // pool and list key
+}
+
+impl AuditLog {
+    pub fn new(capacity: usize, persist: Option<(RedisPool, String)>) -> Self {
+        AuditLog {
+            capacity: capacity.max(1),
+            entries: Mutex::new(VecDeque::new()),
+            persist,
+        }
+    }
+
+    pub fn record(
+        &self,
+        actor: String,
+        action: &str,
+        instrument: Option<&str>,
+        parameters: serde_json::Value,
+    ) {
+        let entry = AuditEntry {
+            timestamp: Utc::now(),
+            action: action.to_string(),
+            instrument: instrument.map(str::to_string),
+            actor,
+            parameters,
+        };
+        println!(
+            "Audit: {} {} {} {}",
+            entry.actor,
+            entry.action,
+            entry.instrument.as_deref().unwrap_or("-"),
+            entry.parameters
+        );
+
+        if let Some((redis_pool, key)) = &self.persist {
+            tokio::spawn(persist_entry(redis_pool.clone(), key.clone(), self.capacity, entry.clone()));
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// Best effort: a Redis outage costs the persisted copy, never the action itself
+async fn persist_entry(redis_pool: RedisPool, key: String, capacity: usize, entry: AuditEntry) {
+    let persisted = async {
+        let json = serde_json::to_string(&entry)?;
+        let mut conn = redis_pool.get().await?;
+        redis::pipe()
+            .cmd("LPUSH")
+            .arg(&key)
+            .arg(json)
+            .ignore()
+            .cmd("LTRIM")
+            .arg(&key)
+            .arg(0)
+            .arg(capacity - 1)
+            .ignore()
+            .query_async::<_, ()>(&mut conn)
+            .await?;
+        Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
+    };
+    if let Err(e) = persisted.await {
+        println!("Warning: Failed to persist audit entry to {}: {}", key, e);
+    }
+}
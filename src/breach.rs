@@ -0,0 +1,174 @@
+use crate::discovery::InstrumentDiscovery;
+use crate::pnl::parse_position;
+use crate::sse::frame_payload;
+use crate::{InstrumentChannels, InstrumentDetails, InstrumentMap};
+use actix_web::web::Bytes;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitType {
+    Absolute, // one instrument's position against its absolute_limit
+    Delta,    // net position across an underlying's instruments against its delta_limit
+}
+
+// A client position that has just gone over one of its limits. Delta breaches belong to
+// the whole underlying and carry no instrument.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Breach {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instrument: Option<String>,
+    pub underlying: String,
+    pub client: String,
+    pub limit_type: LimitType,
+    pub value: f64,
+    pub limit: f64,
+}
+
+impl Breach {
+    pub fn frame(&self) -> Bytes {
+        Bytes::from(format!("event: breach\ndata: {}\n\n", serde_json::json!(self)))
+    }
+}
+
+// Checks position updates against the static data limits. A limit is reported once when
+// it is crossed, and again only after the position has come back within it. A limit of
+// 0 or less means none is set.
+#[derive(Default)]
+pub struct BreachDetector {
+    positions: HashMap<(String, String), f64>, // (client, instrument) -> latest position
+    breached: HashSet<(String, String, LimitType)>, // (client, instrument or underlying, type) over its limit
+}
+
+impl BreachDetector {
+    // New breaches caused by a position_update message. Positions count one-for-one
+    // towards delta; instruments without static data, or for which `skip` is true
+    // (e.g. discovered ones, whose limits are placeholders), are ignored.
+    pub fn apply(
+        &mut self,
+        message: &serde_json::Value,
+        instruments: &HashMap<String, InstrumentDetails>,
+        skip: impl Fn(&str) -> bool,
+    ) -> Vec<Breach> {
+        let client = message.get("client").and_then(|v| v.as_str()).unwrap_or("");
+        let positions = match message.get("data").and_then(|v| v.as_object()) {
+            Some(positions) => positions,
+            None => return Vec::new(),
+        };
+
+        let mut breaches = Vec::new();
+        let mut underlyings: BTreeMap<&str, f64> = BTreeMap::new(); // underlyings touched -> delta limit
+        for (instrument, value) in positions {
+            let (details, position) = match (instruments.get(instrument), parse_position(value)) {
+                (Some(details), Some(entry)) if !skip(instrument) => (details, entry.position),
+                _ => continue,
+            };
+            self.positions.insert((client.to_string(), instrument.clone()), position);
+
+            if self.crossed(client, instrument, LimitType::Absolute, over_limit(position, details.absolute_limit)) {
+                breaches.push(Breach {
+                    instrument: Some(instrument.clone()),
+                    underlying: details.underlying.clone(),
+                    client: client.to_string(),
+                    limit_type: LimitType::Absolute,
+                    value: position,
+                    limit: details.absolute_limit,
+                });
+            }
+            underlyings.entry(details.underlying.as_str()).or_insert(details.delta_limit);
+        }
+
+        for (underlying, delta_limit) in underlyings {
+            let delta: f64 = self
+                .positions
+                .iter()
+                .filter(|((holder, instrument), _)| {
+                    holder == client && instruments.get(instrument).is_some_and(|d| d.underlying == underlying)
+                })
+                .map(|(_, position)| position)
+                .sum();
+            if self.crossed(client, underlying, LimitType::Delta, over_limit(delta, delta_limit)) {
+                breaches.push(Breach {
+                    instrument: None,
+                    underlying: underlying.to_string(),
+                    client: client.to_string(),
+                    limit_type: LimitType::Delta,
+                    value: delta,
+                    limit: delta_limit,
+                });
+            }
+        }
+
+        breaches
+    }
+
+    // Whether a limit has just been crossed, remembering which ones are currently breached
+    fn crossed(&mut self, client: &str, subject: &str, limit_type: LimitType, over: bool) -> bool {
+        let key = (client.to_string(), subject.to_string(), limit_type);
+        if over {
+            self.breached.insert(key)
+        } else {
+            self.breached.remove(&key);
+            false
+        }
+    }
+}
+
+fn over_limit(value: f64, limit: f64) -> bool {
+    limit > 0.0 && value.abs() > limit
+}
+
+// Watch the PnL channel for positions over their limits and send `event: breach` on the
+// instrument's stream, or on every stream of the underlying for delta breaches
+pub async fn breach_detection_loop(
+    mut rx: broadcast::Receiver<Arc<Bytes>>,
+    instrument_details: InstrumentMap,
+    instrument_tx: InstrumentChannels,
+    discovery: Option<Arc<InstrumentDiscovery>>,
+) {
+    let mut detector = BreachDetector::default();
+
+    loop {
+        match rx.recv().await {
+            Ok(msg) => {
+                let message = match frame_payload(&msg) {
+                    Some(message) => message,
+                    None => continue,
+                };
+                if message.get("type").and_then(|v| v.as_str()) != Some("position_update") {
+                    continue;
+                }
+
+                let is_discovered = |instrument: &str| discovery.as_ref().is_some_and(|d| d.is_discovered(instrument));
+                // Channels before details, the order reload and discovery take them in
+                let channels = instrument_tx.read().unwrap();
+                let details = instrument_details.read().unwrap();
+                let breaches = detector.apply(&message, &details, is_discovered);
+                for breach in breaches {
+                    let subject = breach.instrument.as_deref().unwrap_or(&breach.underlying);
+                    println!(
+                        "Warning: {} {:?} limit breached on {}: {} over {}",
+                        breach.client, breach.limit_type, subject, breach.value, breach.limit
+                    );
+                    let frame = Arc::new(breach.frame());
+                    let targets = details.values().filter(|instrument| match &breach.instrument {
+                        Some(name) => &instrument.name == name,
+                        None => instrument.underlying == breach.underlying,
+                    });
+                    for target in targets {
+                        if let Some(tx) = channels.get(&target.name) {
+                            let _ = tx.send(frame.clone().into()); // ignore if no listeners
+                        }
+                    }
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                println!("Warning: Limit breach detection skipped {} position updates", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
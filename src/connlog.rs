@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// How stream connects/disconnects are logged: a line each, or a periodic summary for
+// deployments with enough churn to flood the logs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionLogMode {
+    #[default]
+    PerEvent,
+    Aggregate,
+}
+
+#[derive(Debug, Default)]
+struct InstrumentCounts {
+    connects: u64,    // since the last summary
+    disconnects: u64, // since the last summary
+    active: u64,
+}
+
+pub struct ConnectionLog {
+    mode: ConnectionLogMode,
+    counts: Mutex<BTreeMap<String, InstrumentCounts>>, // only kept in aggregate mode
+}
+
+// Held for the lifetime of a stream; logs or counts the disconnect when dropped
+pub struct ConnectionLogGuard {
+    log: Arc<ConnectionLog>,
+    instruments: Vec<String>,
+}
+
+impl ConnectionLog {
+    pub fn new(mode: ConnectionLogMode) -> Self {
+        ConnectionLog {
+            mode,
+            counts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn mode(&self) -> ConnectionLogMode {
+        self.mode
+    }
+
+    pub fn connected(self: &Arc<Self>, instruments: &[String]) -> ConnectionLogGuard {
+        match self.mode {
+            ConnectionLogMode::PerEvent if instruments.len() == 1 => {
+                println!("SSE connection established for instrument: {}", instruments[0]);
+            }
+            ConnectionLogMode::PerEvent => {
+                println!("SSE connection established for instruments: {}", instruments.join(","));
+            }
+            ConnectionLogMode::Aggregate => {
+                let mut counts = self.counts.lock().unwrap();
+                for instrument in instruments {
+                    let entry = counts.entry(instrument.clone()).or_default();
+                    entry.connects += 1;
+                    entry.active += 1;
+                }
+            }
+        }
+
+        ConnectionLogGuard {
+            log: self.clone(),
+            instruments: instruments.to_vec(),
+        }
+    }
+
+    fn disconnected(&self, instruments: &[String]) {
+        match self.mode {
+            ConnectionLogMode::PerEvent if instruments.len() == 1 => {
+                println!("SSE connection closed for instrument: {}", instruments[0]);
+            }
+            ConnectionLogMode::PerEvent => {
+                println!("SSE connection closed for instruments: {}", instruments.join(","));
+            }
+            ConnectionLogMode::Aggregate => {
+                let mut counts = self.counts.lock().unwrap();
+                for instrument in instruments {
+                    let entry = counts.entry(instrument.clone()).or_default();
+                    entry.disconnects += 1;
+                    entry.active = entry.active.saturating_sub(1);
+                }
+            }
+        }
+    }
+
+    // One line with the connects and disconnects since the last call and the streams
+    // still open, per instrument; resets the interval counts. None when there is nothing
+    // to report.
+    pub fn summary(&self) -> Option<String> {
+        let mut counts = self.counts.lock().unwrap();
+        if counts.is_empty() {
+            return None;
+        }
+
+        let (mut connects, mut disconnects, mut active) = (0, 0, 0);
+        let mut per_instrument = Vec::with_capacity(counts.len());
+        for (instrument, entry) in counts.iter() {
+            connects += entry.connects;
+            disconnects += entry.disconnects;
+            active += entry.active;
+            per_instrument.push(format!(
+                "{} +{} -{} ={}",
+                instrument, entry.connects, entry.disconnects, entry.active
+            ));
+        }
+
+        counts.retain(|_, entry| entry.active > 0);
+        for entry in counts.values_mut() {
+            entry.connects = 0;
+            entry.disconnects = 0;
+        }
+
+        Some(format!(
+            "SSE connections: {} connected, {} disconnected, {} active [{}]",
+            connects,
+            disconnects,
+            active,
+            per_instrument.join(", ")
+        ))
+    }
+}
+
+impl Drop for ConnectionLogGuard {
+    fn drop(&mut self) {
+        self.log.disconnected(&self.instruments);
+    }
+}
+
+// Print an aggregate summary every `interval`; quiet intervals with nothing open print nothing
+pub async fn connection_log_summaries(log: Arc<ConnectionLog>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval.max(Duration::from_secs(1)));
+    interval.tick().await; // the first tick is immediate
+
+    loop {
+        interval.tick().await;
+        if let Some(line) = log.summary() {
+            println!("{}", line);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::replay::ChannelFrame;
+use crate::{
+    classify_instrument, default_display_decimals, instrument_added_frame, ChannelCapacity, InstrumentChannels,
+    InstrumentDetails, InstrumentIndexes, InstrumentMap, StaticDataDefaults,
+};
+use std::collections::{BTreeMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+// Tick size given to discovered instruments, which have no static data
+const DISCOVERED_TICK_SIZE: f64 = 0.01;
+
+// Registers instruments first seen on the live feed, for environments without static
+// data. Discovered instruments get default details and a channel of their own.
+pub struct InstrumentDiscovery {
+    max_instruments: usize,
+    channel_capacity: ChannelCapacity,
+    defaults: StaticDataDefaults,
+    instrument_details: InstrumentMap,
+    instrument_tx: InstrumentChannels,
+    indexes: Arc<InstrumentIndexes>, // refreshed after each registration, as a reload does
+    discovered: Mutex<HashSet<String>>,
+    cap_warned: AtomicBool,
+}
+
+impl InstrumentDiscovery {
+    pub(crate) fn new(
+        max_instruments: usize,
+        channel_capacity: ChannelCapacity,
+        defaults: StaticDataDefaults,
+        instrument_details: InstrumentMap,
+        instrument_tx: InstrumentChannels,
+        indexes: Arc<InstrumentIndexes>,
+    ) -> Self {
+        InstrumentDiscovery {
+            max_instruments,
+            channel_capacity,
+            defaults,
+            instrument_details,
+            instrument_tx,
+            indexes,
+            discovered: Mutex::new(HashSet::new()),
+            cap_warned: AtomicBool::new(false),
+        }
+    }
+
+    // Whether an instrument came from the feed rather than static data
+    pub fn is_discovered(&self, instrument: &str) -> bool {
+        self.discovered.lock().unwrap().contains(instrument)
+    }
+
+    // Register an unknown instrument with default details and announce it as
+    // `event: instruments` on every channel. None once max_instruments are discovered.
+    pub fn register(&self, instrument: &str, underlying: Option<&str>) -> Option<broadcast::Sender<ChannelFrame>> {
+        // The channel map is locked first, as a reload does, so the two never deadlock
+        let mut instrument_tx = self.instrument_tx.write().unwrap();
+        if let Some(tx) = instrument_tx.get(instrument) {
+            return Some(tx.clone());
+        }
+        let mut discovered = self.discovered.lock().unwrap();
+        if discovered.len() >= self.max_instruments {
+            if !self.cap_warned.swap(true, Ordering::Relaxed) {
+                println!(
+                    "Warning: Discovered instrument limit of {} reached, dropping messages for new instruments",
+                    self.max_instruments
+                );
+            }
+            return None;
+        }
+
+        let underlying = underlying.unwrap_or(instrument);
+        let details = InstrumentDetails {
+            name: instrument.to_string(),
+            underlying: underlying.to_string(),
+            absolute_limit: self.defaults.absolute_limit,
+            delta_limit: self.defaults.delta_limit,
+            tick_size: DISCOVERED_TICK_SIZE,
+            max_order_size: self.defaults.max_order_size,
+            display_decimals: default_display_decimals(DISCOVERED_TICK_SIZE),
+            kind: classify_instrument(instrument, underlying),
+            tags: BTreeMap::new(),
+            trading_status: Default::default(),
+            price_band: None,
+        };
+        println!("Discovered instrument {} (underlying {}) from the live feed", instrument, underlying);
+
+        let (tx, _rx) = broadcast::channel::<ChannelFrame>(self.channel_capacity.for_instrument(instrument));
+        instrument_tx.insert(instrument.to_string(), tx.clone());
+        discovered.insert(instrument.to_string());
+
+        let frame = instrument_added_frame(&details);
+        {
+            let mut instrument_details = self.instrument_details.write().unwrap();
+            instrument_details.insert(instrument.to_string(), details);
+            self.indexes.refresh(&instrument_details);
+        }
+        for channel in instrument_tx.values() {
+            let _ = channel.send(frame.clone().into()); // ignore if no listeners
+        }
+
+        Some(tx)
+    }
+}
@@ -0,0 +1,79 @@
+use crate::replay::{ChannelFrame, ReplayBuffer};
+use actix_web::web::Bytes;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// Where an instrument's secondary price lives, read while its primary feed is silent
+pub fn fallback_key(instrument: &str) -> String {
+    format!("fallback:{}", instrument)
+}
+
+fn fallback_frame(instrument: &str, price: f64) -> Arc<Bytes> {
+    Arc::new(Bytes::from(format!(
+        "event: fallback\ndata: {{\"instrument\":{},\"price\":{}}}\n\n",
+        serde_json::json!(instrument),
+        serde_json::json!(price)
+    )))
+}
+
+// Decides which instruments need a fallback price and sends it as `event: fallback`.
+// A price is only re-sent when it changes, and an instrument that ticks live again is
+// dropped until it next goes silent. Instruments that have never ticked count as silent.
+pub struct FallbackTracker {
+    fallback_after: chrono::Duration,
+    sent: HashMap<String, f64>, // last fallback price sent per instrument
+}
+
+impl FallbackTracker {
+    pub fn new(fallback_after: Duration) -> Self {
+        FallbackTracker {
+            fallback_after: chrono::Duration::from_std(fallback_after).unwrap_or(chrono::Duration::MAX),
+            sent: HashMap::new(),
+        }
+    }
+
+    // Instruments with no live tick for longer than fallback_after, sorted by name
+    pub fn silent_instruments(
+        &mut self,
+        replay: &ReplayBuffer,
+        instrument_tx: &HashMap<String, broadcast::Sender<ChannelFrame>>,
+        now: DateTime<Utc>,
+    ) -> Vec<String> {
+        let mut silent: Vec<String> = instrument_tx
+            .keys()
+            .filter(|instrument| match replay.last(instrument, 1).pop() {
+                Some(buffered) => now - buffered.received_at > self.fallback_after,
+                None => true,
+            })
+            .cloned()
+            .collect();
+        silent.sort();
+
+        // Live ticks resumed; the next silence starts over
+        self.sent.retain(|instrument, _| silent.contains(instrument));
+        silent
+    }
+
+    // Send a fallback price unless it was already sent; returns whether it was
+    pub fn publish(
+        &mut self,
+        instrument: &str,
+        price: f64,
+        instrument_tx: &HashMap<String, broadcast::Sender<ChannelFrame>>,
+    ) -> bool {
+        if self.sent.get(instrument) == Some(&price) {
+            return false;
+        }
+        let tx = match instrument_tx.get(instrument) {
+            Some(tx) => tx,
+            None => return false,
+        };
+
+        let _ = tx.send(fallback_frame(instrument, price).into()); // ignore if no listeners
+        self.sent.insert(instrument.to_string(), price);
+        true
+    }
+}
@@ -0,0 +1,368 @@
+use crate::replay::ChannelFrame;
+use crate::{AppState, InstrumentDetails};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, HttpResponse};
+use futures::future::{ready, LocalBoxFuture, Ready};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+// Upper bounds (seconds) of the API latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+#[derive(Default)]
+struct EndpointStats {
+    statuses: BTreeMap<u16, u64>,
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+    latency_count: u64,
+}
+
+// Per-endpoint request counts, status codes and latency, keyed by route pattern
+#[derive(Default)]
+pub struct ApiMetrics {
+    endpoints: Mutex<HashMap<String, EndpointStats>>,
+}
+
+impl ApiMetrics {
+    pub fn record(&self, path: &str, status: u16, elapsed: Duration) {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        let stats = endpoints.entry(path.to_string()).or_default();
+
+        *stats.statuses.entry(status).or_default() += 1;
+
+        // Scraping /metrics would otherwise dominate its own latency histogram
+        if path != "/metrics" {
+            let seconds = elapsed.as_secs_f64();
+            for (bucket, bound) in stats.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+                if seconds <= bound {
+                    *bucket += 1;
+                }
+            }
+            stats.latency_sum += seconds;
+            stats.latency_count += 1;
+        }
+    }
+
+    #[cfg(test)]
+    pub fn request_count(&self, path: &str) -> u64 {
+        let endpoints = self.endpoints.lock().unwrap();
+        endpoints
+            .get(path)
+            .map(|stats| stats.statuses.values().sum())
+            .unwrap_or(0)
+    }
+
+    // Prometheus text exposition of the API request metrics
+    pub fn render(&self, out: &mut String) {
+        let endpoints = self.endpoints.lock().unwrap();
+        let mut paths: Vec<&String> = endpoints.keys().collect();
+        paths.sort();
+
+        let _ = writeln!(out, "# HELP api_requests_total API requests by route and status code");
+        let _ = writeln!(out, "# TYPE api_requests_total counter");
+        for path in &paths {
+            for (status, count) in &endpoints[*path].statuses {
+                let _ = writeln!(
+                    out,
+                    "api_requests_total{{path=\"{}\",status=\"{}\"}} {}",
+                    path, status, count
+                );
+            }
+        }
+
+        let _ = writeln!(out, "# HELP api_request_duration_seconds API request latency by route");
+        let _ = writeln!(out, "# TYPE api_request_duration_seconds histogram");
+        for path in &paths {
+            let stats = &endpoints[*path];
+            if stats.latency_count == 0 {
+                continue;
+            }
+            for (count, bound) in stats.buckets.iter().zip(LATENCY_BUCKETS) {
+                let _ = writeln!(
+                    out,
+                    "api_request_duration_seconds_bucket{{path=\"{}\",le=\"{}\"}} {}",
+                    path, bound, count
+                );
+            }
+            let _ = writeln!(
+                out,
+                "api_request_duration_seconds_bucket{{path=\"{}\",le=\"+Inf\"}} {}",
+                path, stats.latency_count
+            );
+            let _ = writeln!(
+                out,
+                "api_request_duration_seconds_sum{{path=\"{}\"}} {}",
+                path, stats.latency_sum
+            );
+            let _ = writeln!(
+                out,
+                "api_request_duration_seconds_count{{path=\"{}\"}} {}",
+                path, stats.latency_count
+            );
+        }
+    }
+}
+
+// Counters maintained by the market data pump
+#[derive(Default)]
+pub struct FeedMetrics {
+    pub non_finite_dropped: AtomicU64,
+    pub slow_messages: AtomicU64,
+    pub subscribe_failed: AtomicU64,
+    pub dead_connections: AtomicU64,
+    pub messages_forwarded: AtomicU64,
+    pub rejected_ticks: AtomicU64,
+    pub received_by_channel: Mutex<BTreeMap<String, u64>>, // Redis channel/stream -> messages read
+    pub feed_connected: AtomicBool, // set by the pump while its Redis connection is up
+    pub received_by_instrument: Mutex<BTreeMap<String, u64>>, // known instruments only, to bound cardinality
+    pub unknown_instrument_dropped: AtomicU64,
+    pub lag_events: AtomicU64,
+    pub lag_by_instrument: Mutex<BTreeMap<String, u64>>, // lag_events split by instrument, for /sse/{instrument}/diag
+    pub oversized_dropped: AtomicU64, // over max_payload_bytes or max_payload_depth
+    pub truncated_dropped: AtomicU64, // JSON that ends mid-value, cut short by the publisher
+    pub malformed_dropped: AtomicU64, // any other payload that isn't a JSON object
+}
+
+fn increment(counts: &Mutex<BTreeMap<String, u64>>, key: &str) {
+    let mut counts = counts.lock().unwrap();
+    match counts.get_mut(key) {
+        Some(count) => *count += 1,
+        None => {
+            counts.insert(key.to_string(), 1);
+        }
+    }
+}
+
+impl FeedMetrics {
+    pub fn record_received(&self, channel: &str) {
+        increment(&self.received_by_channel, channel);
+    }
+
+    pub fn record_instrument_message(&self, instrument: &str) {
+        increment(&self.received_by_instrument, instrument);
+    }
+
+    pub fn record_lag(&self, instrument: &str) {
+        self.lag_events.fetch_add(1, Ordering::Relaxed);
+        increment(&self.lag_by_instrument, instrument);
+    }
+
+    pub fn instrument_messages(&self, instrument: &str) -> u64 {
+        self.received_by_instrument.lock().unwrap().get(instrument).copied().unwrap_or(0)
+    }
+
+    pub fn instrument_lag_events(&self, instrument: &str) -> u64 {
+        self.lag_by_instrument.lock().unwrap().get(instrument).copied().unwrap_or(0)
+    }
+
+    pub fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP feed_non_finite_dropped_total Ticks dropped for a NaN/infinite price or volume");
+        let _ = writeln!(out, "# TYPE feed_non_finite_dropped_total counter");
+        let _ = writeln!(
+            out,
+            "feed_non_finite_dropped_total {}",
+            self.non_finite_dropped.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(out, "# HELP feed_slow_messages_total Messages whose processing exceeded slow_message_threshold_ms");
+        let _ = writeln!(out, "# TYPE feed_slow_messages_total counter");
+        let _ = writeln!(out, "feed_slow_messages_total {}", self.slow_messages.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP feed_subscribe_failed_total SSE connections refused because the channel was closed");
+        let _ = writeln!(out, "# TYPE feed_subscribe_failed_total counter");
+        let _ = writeln!(out, "feed_subscribe_failed_total {}", self.subscribe_failed.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP feed_dead_connections_total Streams closed because a write stalled past dead_connection_threshold_secs");
+        let _ = writeln!(out, "# TYPE feed_dead_connections_total counter");
+        let _ = writeln!(out, "feed_dead_connections_total {}", self.dead_connections.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP feed_messages_forwarded_total Market data messages forwarded to instrument channels");
+        let _ = writeln!(out, "# TYPE feed_messages_forwarded_total counter");
+        let _ = writeln!(out, "feed_messages_forwarded_total {}", self.messages_forwarded.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP feed_rejected_ticks_total Ticks dropped for a price outside the instrument's band");
+        let _ = writeln!(out, "# TYPE feed_rejected_ticks_total counter");
+        let _ = writeln!(out, "feed_rejected_ticks_total {}", self.rejected_ticks.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP feed_connected Whether the market data pump is connected to Redis");
+        let _ = writeln!(out, "# TYPE feed_connected gauge");
+        let _ = writeln!(out, "feed_connected {}", self.feed_connected.load(Ordering::Relaxed) as u8);
+        let _ = writeln!(out, "# HELP feed_instrument_messages_total Market data messages received from Redis per instrument");
+        let _ = writeln!(out, "# TYPE feed_instrument_messages_total counter");
+        for (instrument, count) in self.received_by_instrument.lock().unwrap().iter() {
+            let _ = writeln!(out, "feed_instrument_messages_total{{instrument=\"{}\"}} {}", instrument, count);
+        }
+        let _ = writeln!(out, "# HELP feed_unknown_instrument_dropped_total Market data messages dropped for an unknown instrument");
+        let _ = writeln!(out, "# TYPE feed_unknown_instrument_dropped_total counter");
+        let _ = writeln!(
+            out,
+            "feed_unknown_instrument_dropped_total {}",
+            self.unknown_instrument_dropped.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(out, "# HELP feed_oversized_dropped_total Market data messages dropped for exceeding max_payload_bytes or max_payload_depth");
+        let _ = writeln!(out, "# TYPE feed_oversized_dropped_total counter");
+        let _ = writeln!(out, "feed_oversized_dropped_total {}", self.oversized_dropped.load(Ordering::Relaxed));
+        let _ = writeln!(out, "# HELP feed_parse_failures_total Market data messages dropped as truncated or otherwise malformed JSON");
+        let _ = writeln!(out, "# TYPE feed_parse_failures_total counter");
+        let _ = writeln!(
+            out,
+            "feed_parse_failures_total{{reason=\"truncated\"}} {}",
+            self.truncated_dropped.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(
+            out,
+            "feed_parse_failures_total{{reason=\"malformed\"}} {}",
+            self.malformed_dropped.load(Ordering::Relaxed)
+        );
+        let _ = writeln!(out, "# HELP sse_lag_events_total Times an instrument SSE subscriber fell behind its channel");
+        let _ = writeln!(out, "# TYPE sse_lag_events_total counter");
+        let _ = writeln!(out, "sse_lag_events_total {}", self.lag_events.load(Ordering::Relaxed));
+    }
+}
+
+// End-of-life snapshot logged on graceful shutdown. Connection counts are the channel
+// receiver counts, which also include internal subscribers such as synthetic baskets.
+pub fn shutdown_summary(
+    instrument_tx: &HashMap<String, broadcast::Sender<ChannelFrame>>,
+    feed_metrics: &FeedMetrics,
+    uptime: Duration,
+) -> serde_json::Value {
+    let connections: BTreeMap<&str, usize> = instrument_tx
+        .iter()
+        .map(|(instrument, tx)| (instrument.as_str(), tx.receiver_count()))
+        .filter(|(_, count)| *count > 0)
+        .collect();
+
+    serde_json::json!({
+        "uptime_secs": uptime.as_secs(),
+        "messages_forwarded": feed_metrics.messages_forwarded.load(Ordering::Relaxed),
+        "active_connections": connections.values().sum::<usize>(),
+        "connections_by_instrument": connections
+    })
+}
+
+// Open subscriptions per instrument channel; like shutdown_summary this counts receivers,
+// so internal subscribers such as synthetic baskets are included
+fn render_subscribers(instrument_tx: &HashMap<String, broadcast::Sender<ChannelFrame>>, out: &mut String) {
+    let subscribers: BTreeMap<&str, usize> = instrument_tx
+        .iter()
+        .map(|(instrument, tx)| (instrument.as_str(), tx.receiver_count()))
+        .collect();
+
+    let _ = writeln!(out, "# HELP sse_subscribers Current subscribers per instrument channel");
+    let _ = writeln!(out, "# TYPE sse_subscribers gauge");
+    for (instrument, count) in subscribers {
+        let _ = writeln!(out, "sse_subscribers{{instrument=\"{}\"}} {}", instrument, count);
+    }
+}
+
+// SSE and other long-lived streams would skew request latency, so they aren't measured
+fn is_streaming_path(path: &str) -> bool {
+    path.starts_with("/sse") || path.starts_with("/stream/")
+}
+
+// Middleware recording ApiMetrics for every non-streaming request
+pub struct RequestMetrics {
+    metrics: Arc<ApiMetrics>,
+}
+
+impl RequestMetrics {
+    pub fn new(metrics: Arc<ApiMetrics>) -> Self {
+        RequestMetrics { metrics }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service,
+            metrics: self.metrics.clone(),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+    metrics: Arc<ApiMetrics>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // Label by route pattern (e.g. /api/instruments/{name}) to keep cardinality bounded
+        let path = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+        let metrics = self.metrics.clone();
+        let started = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            if !is_streaming_path(&path) {
+                metrics.record(&path, res.status().as_u16(), started.elapsed());
+            }
+            Ok(res)
+        })
+    }
+}
+
+// Position limits as gauges, read from the live instrument map so reloads show up on the next scrape
+fn render_instrument_limits(instruments: &HashMap<String, InstrumentDetails>, out: &mut String) {
+    let instruments: BTreeMap<_, _> = instruments.iter().collect();
+    let underlyings: BTreeMap<_, _> = instruments
+        .values()
+        .map(|details| (details.underlying.as_str(), details.delta_limit))
+        .collect();
+
+    let _ = writeln!(out, "# HELP instrument_absolute_limit Absolute position limit per instrument");
+    let _ = writeln!(out, "# TYPE instrument_absolute_limit gauge");
+    for (name, details) in &instruments {
+        let _ = writeln!(out, "instrument_absolute_limit{{instrument=\"{}\"}} {}", name, details.absolute_limit);
+    }
+
+    let _ = writeln!(out, "# HELP instrument_delta_limit Delta limit of the instrument's underlying");
+    let _ = writeln!(out, "# TYPE instrument_delta_limit gauge");
+    for (name, details) in &instruments {
+        let _ = writeln!(out, "instrument_delta_limit{{instrument=\"{}\"}} {}", name, details.delta_limit);
+    }
+
+    let _ = writeln!(out, "# HELP underlying_delta_limit Delta limit per underlying");
+    let _ = writeln!(out, "# TYPE underlying_delta_limit gauge");
+    for (underlying, delta_limit) in underlyings {
+        let _ = writeln!(out, "underlying_delta_limit{{underlying=\"{}\"}} {}", underlying, delta_limit);
+    }
+}
+
+// Prometheus scrape endpoint
+pub async fn metrics_handler(app_state: web::Data<AppState>) -> HttpResponse {
+    let mut body = String::new();
+    app_state.feed_metrics.render(&mut body);
+    render_subscribers(&app_state.instrument_tx.read().unwrap(), &mut body);
+    app_state.api_metrics.render(&mut body);
+    app_state.load_shedder.render(&mut body);
+    if app_state.expose_limit_metrics {
+        render_instrument_limits(&app_state.instrument_details.read().unwrap(), &mut body);
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
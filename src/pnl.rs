@@ -0,0 +1,154 @@
+use crate::sse::{data_frame, frame_payload};
+use crate::{InstrumentDetails, InstrumentMap};
+use actix_web::web::Bytes;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PositionPnl {
+    pub position: f64,
+    pub realized_pnl: f64,
+    pub unrealized_pnl: f64,
+}
+
+// A position_update entry is either a bare quantity or an object carrying PnL fields
+pub(crate) fn parse_position(value: &serde_json::Value) -> Option<PositionPnl> {
+    match value {
+        serde_json::Value::Number(n) => Some(PositionPnl {
+            position: n.as_f64()?,
+            ..Default::default()
+        }),
+        serde_json::Value::Object(fields) => {
+            let field = |name: &str| fields.get(name).and_then(|v| v.as_f64()).unwrap_or(0.0);
+            Some(PositionPnl {
+                position: field("position"),
+                realized_pnl: field("realized_pnl"),
+                unrealized_pnl: field("unrealized_pnl"),
+            })
+        }
+        _ => None,
+    }
+}
+
+// Per-underlying position/PnL totals, maintained incrementally from position updates
+#[derive(Default)]
+pub struct PnlAggregator {
+    positions: HashMap<(String, String), PositionPnl>, // (client, instrument) -> latest entry
+    by_underlying: BTreeMap<String, PositionPnl>,
+}
+
+impl PnlAggregator {
+    // Apply a position_update message; returns true when any underlying's totals changed
+    pub fn apply(
+        &mut self,
+        message: &serde_json::Value,
+        instruments: &HashMap<String, InstrumentDetails>,
+    ) -> bool {
+        let client = message.get("client").and_then(|v| v.as_str()).unwrap_or("");
+        let positions = match message.get("data").and_then(|v| v.as_object()) {
+            Some(positions) => positions,
+            None => return false,
+        };
+
+        let mut changed = false;
+        for (instrument, value) in positions {
+            let new = match parse_position(value) {
+                Some(new) => new,
+                None => continue,
+            };
+
+            let key = (client.to_string(), instrument.clone());
+            let old = self.positions.insert(key, new).unwrap_or_default();
+            if old == new {
+                continue;
+            }
+
+            // Swap the instrument's old contribution for the new one
+            let underlying = instruments
+                .get(instrument)
+                .map(|details| details.underlying.clone())
+                .unwrap_or_else(|| "unknown".to_string());
+            let total = self.by_underlying.entry(underlying).or_default();
+            total.position += new.position - old.position;
+            total.realized_pnl += new.realized_pnl - old.realized_pnl;
+            total.unrealized_pnl += new.unrealized_pnl - old.unrealized_pnl;
+            changed = true;
+        }
+
+        changed
+    }
+
+    pub fn snapshot(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "pnl_by_underlying",
+            "underlyings": self.by_underlying,
+        })
+    }
+}
+
+// Channel plus latest frame for the /sse/pnl/by-underlying stream
+pub struct PnlByUnderlying {
+    tx: broadcast::Sender<Arc<Bytes>>,
+    latest: RwLock<Arc<Bytes>>,
+}
+
+impl Default for PnlByUnderlying {
+    fn default() -> Self {
+        let (tx, _rx) = broadcast::channel::<Arc<Bytes>>(256);
+        PnlByUnderlying {
+            tx,
+            latest: RwLock::new(Arc::new(data_frame(&PnlAggregator::default().snapshot()))),
+        }
+    }
+}
+
+impl PnlByUnderlying {
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Bytes>> {
+        self.tx.subscribe()
+    }
+
+    // The most recent totals, sent in full to newly connected clients
+    pub fn latest(&self) -> Arc<Bytes> {
+        self.latest.read().unwrap().clone()
+    }
+
+    pub fn publish(&self, snapshot: &serde_json::Value) {
+        let frame = Arc::new(data_frame(snapshot));
+        *self.latest.write().unwrap() = frame.clone();
+        let _ = self.tx.send(frame); // ignore if no listeners
+    }
+}
+
+// Recompute per-underlying totals from the PnL channel and publish them on change
+pub async fn pnl_aggregation_loop(
+    mut rx: broadcast::Receiver<Arc<Bytes>>,
+    instrument_details: InstrumentMap,
+    pnl: Arc<PnlByUnderlying>,
+) {
+    let mut aggregator = PnlAggregator::default();
+
+    loop {
+        match rx.recv().await {
+            Ok(msg) => {
+                let message = match frame_payload(&msg) {
+                    Some(message) => message,
+                    None => continue,
+                };
+                if message.get("type").and_then(|v| v.as_str()) != Some("position_update") {
+                    continue;
+                }
+
+                let changed = aggregator.apply(&message, &instrument_details.read().unwrap());
+                if changed {
+                    pnl.publish(&aggregator.snapshot());
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                println!("Warning: PnL aggregation skipped {} position updates", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
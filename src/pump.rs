@@ -0,0 +1,893 @@
+use crate::discovery::InstrumentDiscovery;
+use crate::InstrumentChannels;
+use crate::metrics::FeedMetrics;
+use crate::replay::{ChannelFrame, ReplayBuffer};
+use crate::sse::{data_frame, tag_instrument_event, RECONNECT_FRAME};
+use actix_web::web::Bytes;
+use futures::StreamExt;
+use redis::AsyncCommands;
+use redis::Client as RedisClient;
+use redis::streams::{StreamRangeReply, StreamReadOptions, StreamReadReply};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+// Minimum gap between slow-message warnings; the counter still sees every occurrence
+const SLOW_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+// Enrichment step applied to instrument messages before they are framed
+pub type MessageTransform = Box<dyn Fn(&mut serde_json::Value) + Send + Sync>;
+
+// The first of price/volume holding (or spelling) NaN or +/-Infinity, if any
+fn non_finite_field(json_data: &serde_json::Value) -> Option<&'static str> {
+    ["price", "volume"]
+        .into_iter()
+        .find(|field| match json_data.get(*field) {
+            Some(serde_json::Value::Number(n)) => n.as_f64().is_some_and(|v| !v.is_finite()),
+            Some(serde_json::Value::String(s)) => {
+                s.trim().parse::<f64>().is_ok_and(|v| !v.is_finite())
+            }
+            _ => false,
+        })
+}
+
+// Sanity band for instrument prices: absolute bounds and/or a maximum move from the last
+// accepted price. Ticks outside it are dropped as bad prints.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct PriceBand {
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    #[serde(default)]
+    pub max_move_pct: Option<f64>, // percent from the last accepted price
+}
+
+// instrument -> band, rebuilt from static data whenever the instrument set changes
+pub type PriceBands = Arc<RwLock<HashMap<String, PriceBand>>>;
+
+impl PriceBand {
+    // Why the price is out of band, if it is
+    pub fn check(&self, price: f64, last: Option<f64>) -> Option<String> {
+        if let Some(min) = self.min.filter(|min| price < *min) {
+            return Some(format!("below min {}", min));
+        }
+        if let Some(max) = self.max.filter(|max| price > *max) {
+            return Some(format!("above max {}", max));
+        }
+        if let (Some(max_move_pct), Some(last)) = (self.max_move_pct, last.filter(|last| *last != 0.0)) {
+            let move_pct = ((price - last) / last).abs() * 100.0;
+            if move_pct > max_move_pct {
+                return Some(format!("moved {:.2}% from last {} (max {}%)", move_pct, last, max_move_pct));
+            }
+        }
+        None
+    }
+}
+
+// Bounds checked before a market data message is parsed, so a buggy or malicious
+// publisher can't make the pump spend unbounded time or memory; 0 disables a check
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PayloadLimits {
+    pub max_bytes: usize,
+    pub max_depth: usize, // nested objects/arrays
+}
+
+impl PayloadLimits {
+    // Why a payload breaks the limits, if it does
+    pub fn violation(&self, payload: &str) -> Option<String> {
+        if self.max_bytes > 0 && payload.len() > self.max_bytes {
+            return Some(format!("{} bytes exceeds max_payload_bytes {}", payload.len(), self.max_bytes));
+        }
+        if self.max_depth > 0 && exceeds_depth(payload, self.max_depth) {
+            return Some(format!("nesting exceeds max_payload_depth {}", self.max_depth));
+        }
+        None
+    }
+}
+
+// Byte scan for brackets outside strings, stopping as soon as `max_depth` is passed
+fn exceeds_depth(payload: &str, max_depth: usize) -> bool {
+    let (mut depth, mut in_string, mut escaped) = (0usize, false, false);
+    for byte in payload.bytes() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > max_depth {
+                    return true;
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    false
+}
+
+// Routes parsed market_data messages to the instrument and PnL broadcast channels
+pub struct MarketDataRouter {
+    pub instrument_tx: InstrumentChannels, // shared with AppState, so reloaded and discovered channels are routed to
+    pub pnl_tx: broadcast::Sender<Arc<Bytes>>,
+    pub firehose_tx: Option<broadcast::Sender<Arc<Bytes>>>, // every instrument frame, tagged, when enable_firehose is on
+    pub replay: Arc<ReplayBuffer>,
+    pub feed_metrics: Arc<FeedMetrics>,
+    pub reject_non_finite: bool,
+    pub transforms: Vec<MessageTransform>,
+    pub slow_message_threshold: Duration, // zero disables the alarm
+    pub last_slow_warning: Mutex<Option<Instant>>,
+    pub migrations: ChannelMigrations, // retiring channels still fed during their grace window
+    pub underlying_limit: Option<Arc<UnderlyingRateLimit>>, // shared so the instrument set can be refreshed
+    pub price_bands: PriceBands, // instruments without a band are not checked
+    pub last_prices: Mutex<HashMap<String, f64>>, // last accepted price, for max_move_pct
+    pub payload_limits: PayloadLimits,
+    pub discovery: Option<Arc<InstrumentDiscovery>>, // registers unknown instruments when discover_instruments is on
+    pub join_partial: bool, // reassemble `{"partial": ..., "chunk": ...}` pieces into one message
+    pub partial_pending: Mutex<HashMap<String, String>>, // message id -> chunks received so far of a split message
+}
+
+// Split messages being reassembled at once; pieces of further ones are dropped
+const MAX_PENDING_PARTIALS: usize = 256;
+
+// A piece of a message its publisher split up: `{"partial": true, "chunk": "..."}` for
+// every piece but the last, which has `"partial": false`. Pieces of one message share an
+// `id` so several publishers' messages can interleave. Returns (id, more to come, chunk).
+fn partial_chunk(json_data: &serde_json::Value) -> Option<(&str, bool, &str)> {
+    let id = json_data.get("id").and_then(|v| v.as_str()).unwrap_or("");
+    Some((id, json_data.get("partial")?.as_bool()?, json_data.get("chunk")?.as_str()?))
+}
+
+// Length of an underlying rate-limit window
+const UNDERLYING_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct UnderlyingWindow {
+    started: Option<Instant>,
+    count: u32,
+    throttled: bool,
+    pending: HashMap<String, Arc<Bytes>>, // latest conflated frame per instrument
+}
+
+// Aggregate messages-per-second cap across all instruments of an underlying. Once the cap
+// is hit, each instrument's frames are conflated to the latest one until the window ends.
+pub struct UnderlyingRateLimit {
+    max_per_sec: u32,
+    underlying_of: RwLock<HashMap<String, String>>, // instrument -> underlying
+    windows: Mutex<HashMap<String, UnderlyingWindow>>,
+}
+
+impl UnderlyingRateLimit {
+    pub fn new(max_per_sec: u32, underlying_of: HashMap<String, String>) -> Self {
+        UnderlyingRateLimit {
+            max_per_sec,
+            underlying_of: RwLock::new(underlying_of),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Replace the instrument -> underlying map after the instrument set changes
+    pub fn set_instruments(&self, underlying_of: HashMap<String, String>) {
+        *self.underlying_of.write().unwrap() = underlying_of;
+    }
+
+    // Count a frame against its underlying. None forwards it now; otherwise it is held as
+    // the instrument's latest frame and Some(underlying) is returned if this frame started
+    // the throttling.
+    fn admit(&self, instrument: &str, frame: &Arc<Bytes>, now: Instant) -> Option<Option<String>> {
+        let underlying = self.underlying_of.read().unwrap().get(instrument)?.clone();
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(underlying.clone()).or_default();
+
+        if window.started.is_none_or(|started| now.duration_since(started) >= UNDERLYING_WINDOW) {
+            window.started = Some(now);
+            window.count = 0;
+        }
+        window.count += 1;
+        if window.count <= self.max_per_sec && !window.throttled {
+            return None;
+        }
+
+        window.pending.insert(instrument.to_string(), frame.clone());
+        let started_throttling = !window.throttled;
+        window.throttled = true;
+        Some(started_throttling.then_some(underlying))
+    }
+
+    // Conflated frames of underlyings whose throttled window has ended
+    fn take_due(&self, now: Instant) -> Vec<(String, Arc<Bytes>)> {
+        let mut windows = self.windows.lock().unwrap();
+        let mut due = Vec::new();
+        for window in windows.values_mut() {
+            let ended = window.started.is_some_and(|started| now.duration_since(started) >= UNDERLYING_WINDOW);
+            if window.throttled && ended {
+                window.throttled = false;
+                window.started = Some(now);
+                window.count = window.pending.len() as u32;
+                due.extend(window.pending.drain());
+            }
+        }
+        due
+    }
+
+    // Collected so the map is not locked while frames are sent
+    fn instruments_of(&self, underlying: &str) -> Vec<String> {
+        self.underlying_of
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, u)| u.as_str() == underlying)
+            .map(|(instrument, _)| instrument.clone())
+            .collect()
+    }
+}
+
+fn underlying_throttled_frame(underlying: &str, max_per_sec: u32) -> Arc<Bytes> {
+    let payload = serde_json::json!({"underlying": underlying, "max_messages_per_sec": max_per_sec});
+    Arc::new(Bytes::from(format!("event: underlying_throttled\ndata: {}\n\n", payload)))
+}
+
+// A channel being replaced (e.g. a reload changed channel_capacity). Its subscribers are
+// told to reconnect straight away, but keep receiving frames for the grace window so
+// nothing is missed while they move over to the replacement.
+pub struct ChannelMigration {
+    old: broadcast::Sender<ChannelFrame>,
+    retire_at: Instant,
+}
+
+// Migrations in progress by instrument: started by a static data reload, retired by the pump
+pub type ChannelMigrations = Arc<Mutex<HashMap<String, ChannelMigration>>>;
+
+impl ChannelMigration {
+    // Create the replacement channel and start the grace window on the old one
+    pub fn start(
+        old: broadcast::Sender<ChannelFrame>,
+        capacity: usize,
+        grace: Duration,
+    ) -> (broadcast::Sender<ChannelFrame>, ChannelMigration) {
+        let (new, _rx) = broadcast::channel::<ChannelFrame>(capacity);
+        let _ = old.send(Arc::new(Bytes::from_static(RECONNECT_FRAME)).into());
+        (new, ChannelMigration { old, retire_at: Instant::now() + grace })
+    }
+
+    // Forward a frame to the old channel; false once the grace window is over
+    pub fn forward(&self, frame: &ChannelFrame) -> bool {
+        if Instant::now() >= self.retire_at {
+            return false;
+        }
+        let _ = self.old.send(frame.clone());
+        true
+    }
+}
+
+impl MarketDataRouter {
+    pub fn route(&self, payload: &str) {
+        let started = Instant::now();
+        if let Some(reason) = self.payload_limits.violation(payload) {
+            self.feed_metrics.oversized_dropped.fetch_add(1, Ordering::Relaxed);
+            println!("Warning: Dropping market_data message: {}", reason);
+            return;
+        }
+        let mut json_data = match serde_json::from_str::<serde_json::Value>(payload) {
+            Ok(json_data) if json_data.is_object() => json_data,
+            // Ran out of input mid-value: cut short by the publisher rather than malformed
+            Err(e) if e.is_eof() => {
+                self.feed_metrics.truncated_dropped.fetch_add(1, Ordering::Relaxed);
+                println!("Warning: Dropping truncated market_data message ({} bytes): {}", payload.len(), payload);
+                return;
+            }
+            _ => {
+                self.feed_metrics.malformed_dropped.fetch_add(1, Ordering::Relaxed);
+                println!("Warning: Failed to parse market_data message as a JSON object: {}", payload);
+                return;
+            }
+        };
+
+        if self.join_partial {
+            if let Some((id, more, chunk)) = partial_chunk(&json_data) {
+                return self.join_chunk(id, more, chunk);
+            }
+        }
+
+        self.dispatch(&mut json_data, payload);
+        self.check_processing_time(&json_data, started.elapsed());
+    }
+
+    // Buffer a chunk of a split message, routing the joined message once the last arrives
+    fn join_chunk(&self, id: &str, more: bool, chunk: &str) {
+        let joined = {
+            let mut pending = self.partial_pending.lock().unwrap();
+            if !pending.contains_key(id) && pending.len() >= MAX_PENDING_PARTIALS {
+                println!("Warning: Dropping partial market_data message {}: too many split messages in progress", id);
+                return;
+            }
+            let buffer = pending.entry(id.to_string()).or_default();
+            buffer.push_str(chunk);
+            if self.payload_limits.max_bytes > 0 && buffer.len() > self.payload_limits.max_bytes {
+                self.feed_metrics.oversized_dropped.fetch_add(1, Ordering::Relaxed);
+                println!(
+                    "Warning: Dropping partial market_data message {}: {} bytes exceeds max_payload_bytes {}",
+                    id,
+                    buffer.len(),
+                    self.payload_limits.max_bytes
+                );
+                pending.remove(id);
+                return;
+            }
+            if more {
+                return;
+            }
+            pending.remove(id).unwrap_or_default()
+        };
+        self.route(&joined);
+    }
+
+    // Count (and occasionally log) messages that took longer than the threshold
+    fn check_processing_time(&self, json_data: &serde_json::Value, elapsed: Duration) {
+        if self.slow_message_threshold.is_zero() || elapsed <= self.slow_message_threshold {
+            return;
+        }
+
+        self.feed_metrics.slow_messages.fetch_add(1, Ordering::Relaxed);
+
+        let mut last_warning = self.last_slow_warning.lock().unwrap();
+        if last_warning.is_some_and(|at| at.elapsed() < SLOW_WARNING_INTERVAL) {
+            return;
+        }
+        *last_warning = Some(Instant::now());
+
+        let instrument = json_data.get("instrument").and_then(|v| v.as_str()).unwrap_or("-");
+        println!(
+            "Warning: Slow market_data message for {}: processed in {:?} (threshold {:?})",
+            instrument, elapsed, self.slow_message_threshold
+        );
+    }
+
+    // Dual-forward to a channel being migrated away from, retiring it after its grace window
+    fn forward_to_retiring(&self, instrument: &str, frame: &ChannelFrame) {
+        let mut migrations = self.migrations.lock().unwrap();
+        let expired = match migrations.get(instrument) {
+            Some(migration) => !migration.forward(frame),
+            None => return,
+        };
+        if expired {
+            migrations.remove(instrument); // drops the old sender
+            println!("Retired old channel for {}", instrument);
+        }
+    }
+
+    // Check a tick's price against the instrument's band, rejecting it with a counter, a log
+    // line and an `event: rejected_tick` on the instrument's stream when out of band
+    fn price_in_band(&self, instrument: &str, json_data: &serde_json::Value) -> bool {
+        // Copied out so the map is not locked while the rejection is sent
+        let band = self.price_bands.read().unwrap().get(instrument).copied();
+        let (band, price) = match (
+            band,
+            json_data.get("price").and_then(|v| v.as_f64()),
+        ) {
+            (Some(band), Some(price)) => (band, price),
+            _ => return true,
+        };
+
+        let mut last_prices = self.last_prices.lock().unwrap();
+        let reason = match band.check(price, last_prices.get(instrument).copied()) {
+            Some(reason) => reason,
+            None => {
+                last_prices.insert(instrument.to_string(), price);
+                return true;
+            }
+        };
+
+        self.feed_metrics.rejected_ticks.fetch_add(1, Ordering::Relaxed);
+        println!("Warning: Rejecting tick for {} at {}: {}", instrument, price, reason);
+        if let Some(tx) = self.sender(instrument) {
+            let event = serde_json::json!({"instrument": instrument, "price": price, "reason": reason});
+            let _ = tx.send(Arc::new(Bytes::from(format!("event: rejected_tick\ndata: {}\n\n", event))).into());
+        }
+        false
+    }
+
+    fn sender(&self, instrument: &str) -> Option<broadcast::Sender<ChannelFrame>> {
+        self.instrument_tx.read().unwrap().get(instrument).cloned()
+    }
+
+    // Whether messages for an instrument have somewhere to go, registering it first
+    // when discovery is on
+    fn is_known(&self, instrument: &str, json_data: &serde_json::Value) -> bool {
+        if self.instrument_tx.read().unwrap().contains_key(instrument) {
+            return true;
+        }
+        match &self.discovery {
+            Some(discovery) => discovery
+                .register(instrument, json_data.get("underlying").and_then(|v| v.as_str()))
+                .is_some(),
+            None => false,
+        }
+    }
+
+    // Buffer and broadcast an instrument frame
+    fn publish(&self, instrument: &str, bytes: Arc<Bytes>) {
+        let tx = match self.sender(instrument) {
+            Some(tx) => tx,
+            None => return,
+        };
+        let seq = self.replay.push(instrument, bytes.clone());
+        let frame = ChannelFrame { seq: Some(seq), frame: bytes };
+        self.forward_to_retiring(instrument, &frame);
+        if let Some(firehose_tx) = &self.firehose_tx {
+            let _ = firehose_tx.send(Arc::new(tag_instrument_event(&frame, instrument))); // ignore if no listeners
+        }
+        let _ = tx.send(frame); // ignore if no listeners
+        self.feed_metrics.messages_forwarded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Send the frames conflated while an underlying was over its rate limit, once its window ends
+    pub fn release_conflated(&self, now: Instant) {
+        if let Some(limit) = &self.underlying_limit {
+            for (instrument, bytes) in limit.take_due(now) {
+                self.publish(&instrument, bytes);
+            }
+        }
+    }
+
+    // Apply the per-underlying rate limit, then publish or hold the frame
+    fn publish_limited(&self, instrument: &str, bytes: Arc<Bytes>, now: Instant) {
+        let limit = match &self.underlying_limit {
+            Some(limit) => limit,
+            None => return self.publish(instrument, bytes),
+        };
+
+        self.release_conflated(now);
+        match limit.admit(instrument, &bytes, now) {
+            None => self.publish(instrument, bytes),
+            Some(Some(underlying)) => {
+                println!(
+                    "Warning: Underlying {} exceeded {} messages/sec, conflating its instruments",
+                    underlying, limit.max_per_sec
+                );
+                let frame = underlying_throttled_frame(&underlying, limit.max_per_sec);
+                for affected in limit.instruments_of(&underlying) {
+                    if let Some(tx) = self.sender(&affected) {
+                        let _ = tx.send(frame.clone().into());
+                    }
+                }
+            }
+            Some(None) => {} // already throttled; held as the latest frame
+        }
+    }
+
+    // Buffer a message as the instrument's latest frame without broadcasting it, so it is
+    // replayed as the connect-time snapshot. Returns whether it was seeded.
+    pub fn seed_snapshot(&self, instrument: &str, payload: &str) -> bool {
+        let mut json_data = match serde_json::from_str::<serde_json::Value>(payload) {
+            Ok(json_data) => json_data,
+            Err(_) => return false,
+        };
+        if json_data.get("instrument").and_then(|v| v.as_str()) != Some(instrument)
+            || !self.instrument_tx.read().unwrap().contains_key(instrument)
+        {
+            return false;
+        }
+        if self.reject_non_finite && non_finite_field(&json_data).is_some() {
+            return false;
+        }
+
+        for transform in &self.transforms {
+            transform(&mut json_data);
+        }
+        self.replay.push(instrument, Arc::new(data_frame(&json_data)));
+        true
+    }
+
+    fn dispatch(&self, json_data: &mut serde_json::Value, payload: &str) {
+        // Extract message type from message
+        let msg_type = match json_data.get("type").and_then(|v| v.as_str()) {
+            Some(msg_type) => msg_type.to_string(),
+            None => return,
+        };
+        let msg_type = msg_type.as_str();
+
+        match msg_type {
+            // Handle instrument-specific messages (existing logic)
+            "orderbook_update" | "bbo_update" | "trade" => {
+                let instrument_name = match json_data.get("instrument").and_then(|v| v.as_str()) {
+                    Some(instrument_name) => instrument_name.to_string(),
+                    None => {
+                        println!("Warning: Received {} message without instrument field: {}", msg_type, payload);
+                        return;
+                    }
+                };
+
+                let known = self.is_known(&instrument_name, json_data);
+                if known {
+                    self.feed_metrics.record_instrument_message(&instrument_name);
+                }
+
+                // Non-finite prices turn tick rounding and limit math into garbage
+                if self.reject_non_finite {
+                    if let Some(field) = non_finite_field(json_data) {
+                        self.feed_metrics.non_finite_dropped.fetch_add(1, Ordering::Relaxed);
+                        println!("Warning: Dropping {} message for {} with non-finite {}", msg_type, instrument_name, field);
+                        return;
+                    }
+                }
+
+                if !self.price_in_band(&instrument_name, json_data) {
+                    return;
+                }
+
+                // Route message to appropriate instrument channel
+                if known {
+                    for transform in &self.transforms {
+                        transform(json_data);
+                    }
+                    let bytes = Arc::new(data_frame(json_data));
+                    self.publish_limited(&instrument_name, bytes, Instant::now());
+                } else {
+                    self.feed_metrics.unknown_instrument_dropped.fetch_add(1, Ordering::Relaxed);
+                    println!("Warning: Received message for unknown instrument: {}", instrument_name);
+                }
+            }
+            // Handle position and PnL updates (single channel for all clients)
+            "position_update" | "pnl_update" => {
+                let bytes = Arc::new(data_frame(json_data));
+                let _ = self.pnl_tx.send(bytes); // ignore if no listeners
+            }
+            // Skip unknown message types
+            _ => {}
+        }
+    }
+}
+
+// Exponential backoff between reconnection attempts, doubling from `min` up to `max`
+pub struct ReconnectBackoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+    attempts: u32,
+}
+
+impl ReconnectBackoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        ReconnectBackoff { min, max, next: min, attempts: 0 }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        self.attempts += 1;
+        delay
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    // Back to the shortest delay once a connection is healthy again
+    pub fn reset(&mut self) {
+        self.next = self.min;
+        self.attempts = 0;
+    }
+}
+
+// How long to wait for a message before conflated frames get a chance to be released
+const PUBSUB_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Subscribe and route messages until the connection fails, returning the error
+async fn run_subscription(
+    redis_client: &RedisClient,
+    router: &MarketDataRouter,
+    channels: &[String],
+    backoff: &mut ReconnectBackoff,
+) -> redis::RedisError {
+    let conn = match redis_client.get_async_connection().await {
+        Ok(conn) => conn,
+        Err(e) => return e,
+    };
+
+    // Every channel feeds the same routing
+    let mut pubsub = conn.into_pubsub();
+    if let Err(e) = pubsub.subscribe(channels).await {
+        return e;
+    }
+    if backoff.attempts() > 0 {
+        println!("Reconnected to Redis and resubscribed to {:?}", channels);
+    }
+    backoff.reset();
+    router.feed_metrics.feed_connected.store(true, Ordering::Relaxed);
+
+    let mut messages = pubsub.on_message();
+    loop {
+        match tokio::time::timeout(PUBSUB_POLL_INTERVAL, messages.next()).await {
+            Ok(Some(msg)) => {
+                router.feed_metrics.record_received(msg.get_channel_name());
+                if let Ok(payload) = msg.get_payload::<String>() {
+                    router.route(&payload);
+                } else {
+                    println!("Warning: Failed to get payload as string from Redis message");
+                }
+            }
+            Ok(None) => {
+                return redis::RedisError::from((redis::ErrorKind::IoError, "pub/sub connection closed"));
+            }
+            Err(_) => router.release_conflated(Instant::now()),
+        }
+    }
+}
+
+// Redis pump function for pub/sub message processing. A dropped connection is reopened
+// and resubscribed with exponential backoff (100ms doubling to 5s).
+pub async fn redis_pump(
+    redis_client: RedisClient,
+    router: MarketDataRouter,
+    channels: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut backoff = ReconnectBackoff::new(Duration::from_millis(100), Duration::from_secs(5));
+
+    loop {
+        let error = run_subscription(&redis_client, &router, &channels, &mut backoff).await;
+        router.feed_metrics.feed_connected.store(false, Ordering::Relaxed);
+        let delay = backoff.next_delay();
+        println!(
+            "Warning: Market data subscription lost ({}); reconnect attempt {} in {:?}",
+            error,
+            backoff.attempts(),
+            delay
+        );
+        router.release_conflated(Instant::now());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// Seed each instrument's snapshot from the newest entry of its `{stream_prefix}:{instrument}`
+// stream (XREVRANGE ... COUNT 1). Missing or empty streams are skipped; returns how many
+// instruments were seeded.
+pub async fn preload_snapshots(redis_client: &RedisClient, router: &MarketDataRouter, stream_prefix: &str) -> usize {
+    let mut conn = match redis_client.get_multiplexed_async_connection().await {
+        Ok(conn) => conn,
+        Err(e) => {
+            println!("Warning: Skipping snapshot preload, Redis unavailable: {}", e);
+            return 0;
+        }
+    };
+
+    let instruments: Vec<String> = router.instrument_tx.read().unwrap().keys().cloned().collect();
+    let mut seeded = 0;
+    for instrument in &instruments {
+        let stream_key = format!("{}:{}", stream_prefix, instrument);
+        let reply: StreamRangeReply = match conn.xrevrange_count(&stream_key, "+", "-", 1).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                println!("Warning: Failed to preload snapshot from {}: {}", stream_key, e);
+                continue;
+            }
+        };
+
+        let payload = reply.ids.first().and_then(|entry| entry.get::<String>("payload"));
+        if let Some(payload) = payload {
+            if router.seed_snapshot(instrument, &payload) {
+                seeded += 1;
+            }
+        }
+    }
+    seeded
+}
+
+// Stream entry id "<ms>-<seq>" as a comparable pair
+fn parse_stream_id(id: &str) -> Option<(u64, u64)> {
+    let (ms, seq) = id.split_once('-')?;
+    Some((ms.parse().ok()?, seq.parse().ok()?))
+}
+
+// Last processed stream entry id per instrument, persisted to a Redis hash so a restarted
+// (or reconnecting) stream pump resumes where it left off instead of at "$". Delivery is
+// at-least-once, not exactly-once: entries processed after the last checkpoint flush are
+// processed again after a restart, and reading resumes from the oldest instrument
+// checkpoint, so a long-idle instrument can make the pump rescan (and skip) newer entries.
+#[derive(Debug, Default)]
+pub struct StreamCheckpoints {
+    last: HashMap<String, (u64, u64)>,
+    dirty: bool,
+}
+
+impl StreamCheckpoints {
+    // Restore from HGETALL of the checkpoint hash, ignoring malformed ids
+    pub fn from_saved(saved: HashMap<String, String>) -> Self {
+        let last = saved
+            .into_iter()
+            .filter_map(|(instrument, id)| Some((instrument, parse_stream_id(&id)?)))
+            .collect();
+        StreamCheckpoints { last, dirty: false }
+    }
+
+    // XREAD start id: the oldest checkpoint, or only new entries when there is none
+    pub fn resume_id(&self) -> String {
+        match self.last.values().min() {
+            Some((ms, seq)) => format!("{}-{}", ms, seq),
+            None => "$".to_string(),
+        }
+    }
+
+    // Whether an entry is newer than this instrument's checkpoint
+    pub fn should_process(&self, instrument: &str, id: &str) -> bool {
+        match (parse_stream_id(id), self.last.get(instrument)) {
+            (Some(id), Some(last)) => id > *last,
+            _ => true,
+        }
+    }
+
+    pub fn record(&mut self, instrument: &str, id: &str) {
+        if let Some(id) = parse_stream_id(id) {
+            self.last.insert(instrument.to_string(), id);
+            self.dirty = true;
+        }
+    }
+
+    // Fields to HSET when anything changed since the last flush
+    pub fn take_dirty(&mut self) -> Option<Vec<(String, String)>> {
+        if !self.dirty {
+            return None;
+        }
+        self.dirty = false;
+        Some(
+            self.last
+                .iter()
+                .map(|(instrument, (ms, seq))| (instrument.clone(), format!("{}-{}", ms, seq)))
+                .collect(),
+        )
+    }
+}
+
+// Checkpoint key for an entry: its instrument, or the client for position/PnL messages
+fn checkpoint_key(payload: &str) -> String {
+    let json_data: serde_json::Value = serde_json::from_str(payload).unwrap_or_default();
+    ["instrument", "client"]
+        .iter()
+        .find_map(|field| json_data.get(*field).and_then(|v| v.as_str()))
+        .unwrap_or("_other")
+        .to_string()
+}
+
+// XREAD blocks this long server-side; a reply overdue by STREAM_READ_GRACE on top of it
+// means the connection is dead
+const STREAM_BLOCK: Duration = Duration::from_secs(1);
+const STREAM_READ_GRACE: Duration = Duration::from_secs(5);
+
+// Where a stream consumer is up to, kept across reconnects
+struct StreamPosition {
+    checkpoints: Option<StreamCheckpoints>, // None until restored from Redis
+    last_id: String,
+    last_flush: Instant,
+}
+
+// One stream-consumer connection: restore the saved checkpoints (first connection only),
+// then XREAD until something fails, returning the error for the caller to back off on
+async fn run_stream_consumer(
+    redis_client: &RedisClient,
+    router: &MarketDataRouter,
+    stream_key: &str,
+    checkpoint_interval: Duration,
+    position: &mut StreamPosition,
+    backoff: &mut ReconnectBackoff,
+) -> redis::RedisError {
+    let checkpoint_key_name = format!("checkpoint:{}", stream_key);
+    let mut conn = match redis_client.get_multiplexed_async_connection().await {
+        Ok(conn) => conn,
+        Err(e) => return e,
+    };
+
+    let checkpoints = match position.checkpoints.as_mut() {
+        // Resume from the in-memory checkpoints, which are never older than the saved ones
+        Some(checkpoints) => {
+            if position.last_id == "$" {
+                position.last_id = checkpoints.resume_id();
+            }
+            println!("Reconnected to Redis, resuming stream {} from {}", stream_key, position.last_id);
+            checkpoints
+        }
+        None => {
+            let saved: redis::RedisResult<HashMap<String, String>> =
+                redis::cmd("HGETALL").arg(&checkpoint_key_name).query_async(&mut conn).await;
+            let saved = match saved {
+                Ok(saved) => saved,
+                Err(e) => return e,
+            };
+            let checkpoints = StreamCheckpoints::from_saved(saved);
+            position.last_id = checkpoints.resume_id();
+            println!("Consuming stream {} from {}", stream_key, position.last_id);
+            position.checkpoints.insert(checkpoints)
+        }
+    };
+    backoff.reset();
+    router.feed_metrics.feed_connected.store(true, Ordering::Relaxed);
+
+    let options = StreamReadOptions::default().block(STREAM_BLOCK.as_millis() as usize).count(500);
+    loop {
+        let keys = [stream_key];
+        let ids = [&position.last_id];
+        let read = conn.xread_options(&keys, &ids, &options);
+        let reply: StreamReadReply = match crate::bounded(STREAM_BLOCK + STREAM_READ_GRACE, read).await {
+            Ok(reply) => reply,
+            Err(e) => return e,
+        };
+
+        for entry in reply.keys.into_iter().flat_map(|key| key.ids) {
+            router.feed_metrics.record_received(stream_key);
+            if let Some(payload) = entry.get::<String>("payload") {
+                let key = checkpoint_key(&payload);
+                if checkpoints.should_process(&key, &entry.id) {
+                    router.route(&payload);
+                    checkpoints.record(&key, &entry.id);
+                }
+            }
+            position.last_id = entry.id;
+        }
+        router.release_conflated(Instant::now());
+
+        if position.last_flush.elapsed() >= checkpoint_interval {
+            position.last_flush = Instant::now();
+            if let Some(fields) = checkpoints.take_dirty() {
+                let saved: redis::RedisResult<()> = redis::cmd("HSET")
+                    .arg(&checkpoint_key_name)
+                    .arg(fields)
+                    .query_async(&mut conn)
+                    .await;
+                if let Err(e) = saved {
+                    println!("Warning: Failed to save stream checkpoints: {}", e);
+                }
+            }
+        }
+    }
+}
+
+// Stream-consumer ingestion: XREAD the market data stream (entries carry the same JSON as
+// the pub/sub channel in a `payload` field), checkpointing progress to
+// `checkpoint:{stream_key}` every `checkpoint_interval`. A failed connect, checkpoint
+// restore or read is retried with exponential backoff (100ms doubling to 5s).
+pub async fn redis_stream_pump(
+    redis_client: RedisClient,
+    router: MarketDataRouter,
+    stream_key: String,
+    checkpoint_interval: Duration,
+) {
+    let mut backoff = ReconnectBackoff::new(Duration::from_millis(100), Duration::from_secs(5));
+    let mut position = StreamPosition {
+        checkpoints: None,
+        last_id: "$".to_string(),
+        last_flush: Instant::now(),
+    };
+
+    loop {
+        let error = run_stream_consumer(
+            &redis_client,
+            &router,
+            &stream_key,
+            checkpoint_interval,
+            &mut position,
+            &mut backoff,
+        )
+        .await;
+        router.feed_metrics.feed_connected.store(false, Ordering::Relaxed);
+        let delay = backoff.next_delay();
+        println!(
+            "Warning: Market data stream {} lost ({}); reconnect attempt {} in {:?}",
+            stream_key,
+            error,
+            backoff.attempts(),
+            delay
+        );
+        router.release_conflated(Instant::now());
+        tokio::time::sleep(delay).await;
+    }
+}
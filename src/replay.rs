@@ -0,0 +1,210 @@
+use actix_web::web::Bytes;
+use chrono::{DateTime, NaiveTime, Utc};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+// A broadcast frame retained for replay, tagged with its per-instrument sequence id
+#[derive(Debug, Clone)]
+pub struct BufferedFrame {
+    pub seq: u64,
+    pub received_at: DateTime<Utc>,
+    pub frame: Arc<Bytes>,
+}
+
+// A frame as broadcast on an instrument channel. Frames recorded here carry the sequence id
+// they were given, so subscribers don't have to look it up; events carry none.
+#[derive(Debug, Clone)]
+pub struct ChannelFrame {
+    pub seq: Option<u64>,
+    pub frame: Arc<Bytes>,
+}
+
+impl From<Arc<Bytes>> for ChannelFrame {
+    fn from(frame: Arc<Bytes>) -> Self {
+        ChannelFrame { seq: None, frame }
+    }
+}
+
+impl std::ops::Deref for ChannelFrame {
+    type Target = Bytes;
+
+    fn deref(&self) -> &Bytes {
+        &self.frame
+    }
+}
+
+#[derive(Default)]
+struct InstrumentBuffer {
+    next_seq: u64,
+    frames: VecDeque<BufferedFrame>,
+}
+
+// Frames to replay for a client resuming after a Last-Event-ID
+pub struct Resume {
+    pub frames: Vec<BufferedFrame>, // newer than the requested id, oldest first
+    pub gap: Option<u64>, // oldest retained seq, when frames the client missed were evicted
+    pub through: u64, // newest seq assigned so far; live frames up to it are already covered
+}
+
+// Ring buffer of the most recent frames per instrument, fed by redis_pump. This is also
+// where each instrument's last update time lives, so it is the map to bound.
+pub struct ReplayBuffer {
+    capacity: usize,
+    max_instruments: usize, // 0 = unbounded
+    instruments: Mutex<HashMap<String, InstrumentBuffer>>,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self::with_max_instruments(capacity, 0)
+    }
+
+    // Once `max_instruments` are tracked, a new instrument evicts the least recently
+    // updated one. Its sequence ids start over if it ticks again.
+    pub fn with_max_instruments(capacity: usize, max_instruments: usize) -> Self {
+        ReplayBuffer {
+            capacity: capacity.max(1),
+            max_instruments,
+            instruments: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Record a frame, evicting the oldest once full, and return its sequence id
+    pub fn push(&self, instrument: &str, frame: Arc<Bytes>) -> u64 {
+        let mut instruments = self.instruments.lock().unwrap();
+        if self.max_instruments > 0
+            && instruments.len() >= self.max_instruments
+            && !instruments.contains_key(instrument)
+        {
+            let least_recent = instruments
+                .iter()
+                .min_by_key(|(_, buffer)| buffer.frames.back().map(|last| last.received_at))
+                .map(|(name, _)| name.clone());
+            if let Some(evicted) = least_recent {
+                instruments.remove(&evicted);
+                println!("Warning: Tracking more than {} instruments, evicted {}", self.max_instruments, evicted);
+            }
+        }
+        let buffer = instruments.entry(instrument.to_string()).or_default();
+
+        buffer.next_seq += 1;
+        let seq = buffer.next_seq;
+
+        if buffer.frames.len() == self.capacity {
+            buffer.frames.pop_front();
+        }
+        buffer.frames.push_back(BufferedFrame {
+            seq,
+            received_at: Utc::now(),
+            frame,
+        });
+
+        seq
+    }
+
+    // The most recent `count` frames for an instrument, oldest first
+    pub fn last(&self, instrument: &str, count: usize) -> Vec<BufferedFrame> {
+        let instruments = self.instruments.lock().unwrap();
+        match instruments.get(instrument) {
+            Some(buffer) => {
+                let skip = buffer.frames.len().saturating_sub(count);
+                buffer.frames.iter().skip(skip).cloned().collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // Frames currently retained for an instrument
+    pub fn buffered(&self, instrument: &str) -> usize {
+        let instruments = self.instruments.lock().unwrap();
+        instruments.get(instrument).map_or(0, |buffer| buffer.frames.len())
+    }
+
+    // Forget instruments that are no longer in the universe (e.g. delisted by a reload),
+    // returning how many were dropped
+    pub fn retain_instruments(&self, keep: impl Fn(&str) -> bool) -> usize {
+        let mut instruments = self.instruments.lock().unwrap();
+        let before = instruments.len();
+        instruments.retain(|name, _| keep(name));
+        before - instruments.len()
+    }
+
+    // Frames after `last_id`. An id ahead of anything assigned (e.g. from before a
+    // restart) is unknown, so everything retained is replayed and reported as a gap.
+    pub fn since(&self, instrument: &str, last_id: u64) -> Resume {
+        let instruments = self.instruments.lock().unwrap();
+        let buffer = match instruments.get(instrument) {
+            Some(buffer) => buffer,
+            None => {
+                return Resume {
+                    frames: Vec::new(),
+                    gap: None,
+                    through: 0,
+                }
+            }
+        };
+
+        let last_id = if last_id > buffer.next_seq { 0 } else { last_id };
+        let gap = buffer
+            .frames
+            .front()
+            .map(|oldest| oldest.seq)
+            .filter(|oldest| *oldest > last_id + 1);
+        Resume {
+            frames: buffer.frames.iter().filter(|f| f.seq > last_id).cloned().collect(),
+            gap,
+            through: buffer.next_seq,
+        }
+    }
+}
+
+// Trading session (UTC time of day) bounding what is replayed on connect, so clients
+// don't get overnight ticks as "history"
+#[derive(Debug, Clone, Copy)]
+pub struct ReplaySession {
+    start: NaiveTime,
+    end: Option<NaiveTime>,
+}
+
+impl ReplaySession {
+    // Times are "HH:MM" or "HH:MM:SS"
+    pub fn parse(start: &str, end: Option<&str>) -> Result<Self, String> {
+        let parse_time = |value: &str| {
+            NaiveTime::parse_from_str(value, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+                .map_err(|_| format!("Invalid replay session time {:?}; expected HH:MM", value))
+        };
+
+        let start = parse_time(start)?;
+        let end = end.map(parse_time).transpose()?;
+        if end.is_some_and(|end| end <= start) {
+            return Err("replay_session_end must be after replay_session_start".to_string());
+        }
+
+        Ok(ReplaySession { start, end })
+    }
+
+    // Start of the session in progress at `now`, or None outside market hours
+    fn current_start(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let time = now.time();
+        if time < self.start || self.end.is_some_and(|end| time >= end) {
+            return None;
+        }
+        Some(now.date_naive().and_time(self.start).and_utc())
+    }
+
+    // Keep only frames received during the current session
+    pub fn filter(&self, mut frames: Vec<BufferedFrame>, now: DateTime<Utc>) -> Vec<BufferedFrame> {
+        match self.current_start(now) {
+            Some(start) => {
+                frames.retain(|frame| frame.received_at >= start);
+                frames
+            }
+            None => Vec::new(),
+        }
+    }
+}
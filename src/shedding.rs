@@ -0,0 +1,99 @@
+use actix_web::HttpResponse;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Connection-count load shedding with hysteresis: once `high_water` streams are open, new
+// ones are refused until the count falls back to `low_water`. Existing streams are never cut.
+pub struct LoadShedder {
+    high_water: usize, // 0 disables shedding
+    low_water: usize,
+    active: AtomicUsize,
+    shedding: AtomicBool,
+}
+
+// Held for the lifetime of an admitted stream
+pub struct ConnectionGuard {
+    shedder: Arc<LoadShedder>,
+}
+
+impl LoadShedder {
+    pub fn new(high_water: usize, low_water: usize) -> Self {
+        let low_water = if low_water > high_water {
+            println!(
+                "Warning: shed_low_water_mark {} exceeds shed_high_water_mark {}; using {}",
+                low_water, high_water, high_water
+            );
+            high_water
+        } else {
+            low_water
+        };
+
+        LoadShedder {
+            high_water,
+            low_water,
+            active: AtomicUsize::new(0),
+            shedding: AtomicBool::new(false),
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn is_shedding(&self) -> bool {
+        self.shedding.load(Ordering::Relaxed)
+    }
+
+    // Flip the shedding state when the count crosses a water mark
+    fn update(&self, active: usize) {
+        if self.high_water == 0 {
+            return;
+        }
+
+        if !self.is_shedding() && active >= self.high_water {
+            self.shedding.store(true, Ordering::Relaxed);
+            println!("Warning: Load shedding started with {} active connections", active);
+        } else if self.is_shedding() && active <= self.low_water {
+            self.shedding.store(false, Ordering::Relaxed);
+            println!("Load shedding stopped with {} active connections", active);
+        }
+    }
+
+    // Admit a new stream, or None while shedding
+    pub fn try_admit(self: &Arc<Self>) -> Option<ConnectionGuard> {
+        self.update(self.active());
+        if self.is_shedding() {
+            return None;
+        }
+
+        let active = self.active.fetch_add(1, Ordering::Relaxed) + 1;
+        self.update(active);
+        Some(ConnectionGuard { shedder: self.clone() })
+    }
+
+    pub fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP stream_active_connections Open streaming connections");
+        let _ = writeln!(out, "# TYPE stream_active_connections gauge");
+        let _ = writeln!(out, "stream_active_connections {}", self.active());
+        let _ = writeln!(out, "# HELP stream_load_shedding Whether new streaming connections are being refused");
+        let _ = writeln!(out, "# TYPE stream_load_shedding gauge");
+        let _ = writeln!(out, "stream_load_shedding {}", self.is_shedding() as u8);
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let active = self.shedder.active.fetch_sub(1, Ordering::Relaxed) - 1;
+        self.shedder.update(active);
+    }
+}
+
+pub fn overloaded() -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .insert_header(("Retry-After", "5"))
+        .json(serde_json::json!({
+            "error": "overloaded",
+            "hint": "Too many streaming connections; retry shortly"
+        }))
+}
@@ -1,41 +1,1321 @@
-use crate::AppState;
+use crate::metrics::FeedMetrics;
+use crate::replay::ChannelFrame;
+use crate::shedding::{overloaded, LoadShedder};
+use crate::{AppState, FxRates};
 use actix_web::web::Bytes;
-use actix_web::{web, Error, HttpResponse};
+use actix_web::http::{header, Method};
+use actix_web::{guard, web, Error, HttpRequest, HttpResponse};
 use async_stream::stream;
-use tokio::sync::broadcast;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SseQuery {
+    currency: Option<String>,
+    #[serde(default)]
+    diff: bool, // send only changed top-level fields after the first full frame
+    #[serde(default)]
+    format: StreamFormat,
+    #[serde(default)]
+    compress_snapshot: bool, // send connect-time snapshots as `event: snapshot_gz`
+    #[serde(default)]
+    include_ui_hints: bool, // add the instrument's static data tags to each frame
+    sample: Option<String>, // "1/N": forward only every Nth data frame
+    #[serde(default)]
+    checksum: bool, // add a `: crc=<hex>` comment to each data frame (json format only)
+    fields: Option<String>, // "price,volume": send only these top-level fields, plus `instrument`
+    conflate_ms: Option<u64>, // send at most the newest data frame per this many ms; 0 or absent sends all
+}
+
+// Parse a `sample=1/N` downsampling ratio into N
+pub(crate) fn parse_sample(sample: &str) -> Option<u64> {
+    let (numerator, every) = sample.split_once('/')?;
+    match (numerator.trim(), every.trim().parse::<u64>()) {
+        ("1", Ok(every)) if every > 0 => Some(every),
+        _ => None,
+    }
+}
+
+// Parse a `fields=price,volume` list; None when it names no field
+pub(crate) fn parse_fields(fields: &str) -> Option<HashSet<String>> {
+    let fields: HashSet<String> = fields
+        .split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(str::to_string)
+        .collect();
+    (!fields.is_empty()).then_some(fields)
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct SnapshotQuery {
+    #[serde(default)]
+    compress_snapshot: bool,
+}
+
+// Per-connection encoding of outbound frames, chosen with `?format=`:
+// - `json` (default): standard SSE, `data: <json>\n\n`
+// - `ndjson`: one JSON document per line with no SSE framing; named events become
+//   `{"event": <name>, "data": <payload>}` and heartbeats become blank lines
+// - `msgpack-base64`: SSE framing kept, but each `data:` line carries the standard
+//   (padded) base64 of the MessagePack-encoded payload, so it stays text-safe.
+//   Clients base64-decode the data line and then MessagePack-decode the bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StreamFormat {
+    #[default]
+    Json,
+    Ndjson,
+    MsgpackBase64,
+}
+
+impl StreamFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            StreamFormat::Json | StreamFormat::MsgpackBase64 => "text/event-stream",
+            StreamFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    // Re-encode one SSE frame for this connection
+    pub(crate) fn encode(self, frame: &[u8]) -> Bytes {
+        match self {
+            StreamFormat::Json => Bytes::copy_from_slice(frame),
+            StreamFormat::Ndjson => ndjson_line(frame),
+            StreamFormat::MsgpackBase64 => msgpack_base64_frame(frame),
+        }
+    }
+}
+
+// Split an SSE frame into its non-data lines (event/id/comments) and its data payload
+fn split_frame(frame: &[u8]) -> (Vec<&str>, Option<&str>) {
+    let text = std::str::from_utf8(frame).unwrap_or("");
+    let mut fields = Vec::new();
+    let mut data = None;
+
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        match line.strip_prefix("data: ") {
+            Some(payload) => data = Some(payload),
+            None => fields.push(line),
+        }
+    }
+
+    (fields, data)
+}
+
+fn ndjson_line(frame: &[u8]) -> Bytes {
+    let (fields, data) = split_frame(frame);
+    let data = match data {
+        Some(data) => data,
+        None => return Bytes::from_static(b"\n"), // heartbeats and comments keep the line alive
+    };
+
+    match fields.iter().find_map(|line| line.strip_prefix("event: ")) {
+        None => Bytes::from(format!("{}\n", data)),
+        Some(event) => {
+            let payload: serde_json::Value = serde_json::from_str(data)
+                .unwrap_or_else(|_| serde_json::Value::String(data.to_string()));
+            Bytes::from(format!("{}\n", serde_json::json!({"event": event, "data": payload})))
+        }
+    }
+}
+
+fn msgpack_base64_frame(frame: &[u8]) -> Bytes {
+    let (fields, data) = split_frame(frame);
+    let packed = data
+        .and_then(|data| serde_json::from_str::<serde_json::Value>(data).ok())
+        .and_then(|payload| rmp_serde::to_vec(&payload).ok());
+    let packed = match packed {
+        Some(packed) => packed,
+        None => return Bytes::copy_from_slice(frame), // nothing to encode (e.g. a heartbeat)
+    };
+
+    let mut encoded = String::new();
+    for field in fields {
+        encoded.push_str(field);
+        encoded.push('\n');
+    }
+    encoded.push_str("data: ");
+    encoded.push_str(&BASE64.encode(packed));
+    encoded.push_str("\n\n");
+    Bytes::from(encoded)
+}
+
+// Parse the JSON payload out of a `data: ...` SSE frame
+pub(crate) fn frame_payload(frame: &[u8]) -> Option<serde_json::Value> {
+    let text = std::str::from_utf8(frame).ok()?;
+    let data = text.strip_prefix("data: ")?.trim_end();
+    serde_json::from_str(data).ok()
+}
+
+// Format a JSON payload as a `data: ...` SSE frame
+pub(crate) fn data_frame(payload: &serde_json::Value) -> Bytes {
+    Bytes::from(format!("data: {}\n\n", payload))
+}
+
+// Multiply the frame's price by the FX rate and tag it with the currency
+pub(crate) fn convert_currency(frame: &[u8], currency: &str, rate: f64) -> Option<Bytes> {
+    let mut payload = frame_payload(frame)?;
+    let price = payload.get("price").and_then(|v| v.as_f64())?;
+    payload["price"] = serde_json::json!(price * rate);
+    payload["currency"] = serde_json::json!(currency);
+    Some(data_frame(&payload))
+}
+
+// Keep only the requested top-level fields of a data frame, plus `instrument`; requested
+// fields the message lacks are left out. Frames without a JSON object payload are None.
+pub(crate) fn select_fields(frame: &[u8], fields: &HashSet<String>) -> Option<Bytes> {
+    let mut payload = frame_payload(frame)?;
+    payload
+        .as_object_mut()?
+        .retain(|field, _| field == "instrument" || fields.contains(field));
+    Some(data_frame(&payload))
+}
+
+// Build a partial update holding only the top-level fields that changed since the
+// last frame, plus `instrument`. Fields that disappeared are listed under `_removed`.
+// Returns None when nothing changed.
+pub(crate) fn diff_payload(
+    previous: Option<&serde_json::Value>,
+    current: &serde_json::Value,
+) -> Option<serde_json::Value> {
+    let (previous, current_fields) = match (previous.and_then(|p| p.as_object()), current.as_object()) {
+        (Some(previous), Some(current_fields)) => (previous, current_fields),
+        _ => return Some(current.clone()), // first frame (or non-object) is always sent in full
+    };
+
+    let mut partial = serde_json::Map::new();
+    for (key, value) in current_fields {
+        if previous.get(key) != Some(value) {
+            partial.insert(key.clone(), value.clone());
+        }
+    }
+
+    let removed: Vec<&String> = previous
+        .keys()
+        .filter(|key| !current_fields.contains_key(*key))
+        .collect();
+
+    if partial.is_empty() && removed.is_empty() {
+        return None;
+    }
+
+    if let Some(instrument) = current_fields.get("instrument") {
+        partial.insert("instrument".to_string(), instrument.clone());
+    }
+    if !removed.is_empty() {
+        partial.insert("_removed".to_string(), serde_json::json!(removed));
+    }
+
+    Some(serde_json::Value::Object(partial))
+}
+
+// Add an integrity comment after a data frame's data line. The checksum is the CRC32
+// (IEEE, as in zlib/gzip) of the data line's UTF-8 bytes after the `data: ` prefix, up to
+// but not including the newline, written as 8 lowercase hex digits:
+//   data: {"instrument":"AAPL","price":1}
+//   : crc=1a2b3c4d
+// Standard EventSource parsers ignore comment lines. Other frames are returned unchanged.
+pub(crate) fn checksum_frame(frame: &Bytes) -> Bytes {
+    let line_end = match frame.iter().position(|b| *b == b'\n') {
+        Some(line_end) if frame.starts_with(b"data: ") => line_end,
+        _ => return frame.clone(),
+    };
+
+    let crc = crc32fast::hash(&frame[b"data: ".len()..line_end]);
+    let mut tagged = frame[..=line_end].to_vec();
+    tagged.extend_from_slice(format!(": crc={:08x}\n", crc).as_bytes());
+    tagged.extend_from_slice(&frame[line_end + 1..]);
+    Bytes::from(tagged)
+}
+
+// Per-connection transforms applied, in order, to each outbound data frame
+#[derive(Default)]
+pub(crate) struct FramePipeline {
+    pub(crate) fx: Option<(String, f64)>, // currency and last known rate
+    pub(crate) fx_rates: FxRates,
+    pub(crate) diff: bool,
+    pub(crate) last_sent: Option<serde_json::Value>,
+    pub(crate) ui_hints: Option<serde_json::Value>, // instrument tags added as `ui_hints`
+    pub(crate) sample_every: u64, // forward the 1st, (N+1)th, ... data frame; 0 or 1 keeps all
+    pub(crate) sample_seen: u64,
+    pub(crate) fields: Option<HashSet<String>>, // `?fields=` selection; None passes frames untouched
+    pub(crate) checksum: bool, // append a CRC comment, after every other transform
+}
+
+impl FramePipeline {
+    // Returns None when the frame should not be sent on this connection
+    pub(crate) fn apply(&mut self, frame: &Bytes) -> Option<Bytes> {
+        let mut frame = frame.clone();
+
+        // Downsample data frames only; events (warn, reconnect, ...) always pass through.
+        // Runs first so diffs are taken against frames the client actually received.
+        if self.sample_every > 1 && frame.starts_with(b"data: ") {
+            self.sample_seen += 1;
+            if !(self.sample_seen - 1).is_multiple_of(self.sample_every) {
+                return None;
+            }
+        }
+
+        // Select fields before the per-connection annotations below, which are kept
+        if let Some(fields) = &self.fields {
+            if let Some(selected) = select_fields(&frame, fields) {
+                frame = selected;
+            }
+        }
+
+        if let Some((currency, rate)) = self.fx.as_mut() {
+            // Pick up refreshed rates, keeping the last known one otherwise
+            if let Some(latest) = self.fx_rates.read().unwrap().get(currency.as_str()).copied() {
+                *rate = latest;
+            }
+            if let Some(converted) = convert_currency(&frame, currency, *rate) {
+                frame = converted;
+            }
+        }
+
+        if let Some(hints) = &self.ui_hints {
+            if let Some(mut payload) = frame_payload(&frame) {
+                if let Some(fields) = payload.as_object_mut() {
+                    fields.insert("ui_hints".to_string(), hints.clone());
+                    frame = data_frame(&payload);
+                }
+            }
+        }
+
+        if self.diff {
+            if let Some(payload) = frame_payload(&frame) {
+                let partial = diff_payload(self.last_sent.as_ref(), &payload);
+                self.last_sent = Some(payload);
+                frame = data_frame(&partial?);
+            }
+        }
+
+        if self.checksum {
+            frame = checksum_frame(&frame);
+        }
+
+        Some(frame)
+    }
+}
+
+// A live frame as sent on this connection, tagged with its replay seq when it has one
+fn live_frame(pipeline: &mut FramePipeline, msg: &Bytes, seq: Option<u64>) -> Option<Bytes> {
+    let frame = pipeline.apply(msg)?;
+    Some(match seq {
+        Some(seq) => with_event_id(&frame, seq),
+        None => frame,
+    })
+}
+
+// Comment frame written while a stream is otherwise quiet, so proxies and load
+// balancers don't time the connection out
+pub(crate) const HEARTBEAT_FRAME: &[u8] = b": keep-alive\n\n";
+
+// With `heartbeat_health`, heartbeats become `: hb <ok|degraded> feed=<up|down>` so idle
+// clients can see whether the server is shedding load and the feed is connected
+pub(crate) struct HeartbeatHealth {
+    feed_metrics: Arc<FeedMetrics>,
+    load_shedder: Arc<LoadShedder>,
+}
+
+impl HeartbeatHealth {
+    pub(crate) fn from_state(app_state: &AppState) -> Option<Self> {
+        app_state.heartbeat_health.then(|| HeartbeatHealth {
+            feed_metrics: app_state.feed_metrics.clone(),
+            load_shedder: app_state.load_shedder.clone(),
+        })
+    }
+
+    fn frame(&self) -> Bytes {
+        let server = if self.load_shedder.is_shedding() { "degraded" } else { "ok" };
+        let feed = if self.feed_metrics.feed_connected.load(Ordering::Relaxed) { "up" } else { "down" };
+        Bytes::from(format!(": hb {} feed={}\n\n", server, feed))
+    }
+}
+
+// The frame for the next heartbeat tick
+pub(crate) fn heartbeat_frame(health: Option<&HeartbeatHealth>) -> Bytes {
+    match health {
+        Some(health) => health.frame(),
+        None => Bytes::from_static(HEARTBEAT_FRAME),
+    }
+}
+
+// Periodic timer for a stream (heartbeats, conflation); its first tick is one period
+// after connecting. With no period the caller never polls it.
+pub(crate) fn heartbeat_ticker(period: Option<std::time::Duration>) -> tokio::time::Interval {
+    let period = period.unwrap_or(std::time::Duration::from_secs(3600));
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker
+}
+
+// Prefix a frame with its `id:` line; clients send the last one back as Last-Event-ID
+pub(crate) fn with_event_id(frame: &[u8], seq: u64) -> Bytes {
+    let mut tagged = format!("id: {}\n", seq).into_bytes();
+    tagged.extend_from_slice(frame);
+    Bytes::from(tagged)
+}
+
+// Warns a resuming client that frames between its Last-Event-ID and the oldest retained
+// one were evicted and can't be replayed
+pub(crate) fn gap_frame(last_event_id: u64, first_available: u64) -> Bytes {
+    Bytes::from(format!(
+        "event: warn\ndata: {}\n\n",
+        serde_json::json!({"gap": true, "last_event_id": last_event_id, "first_available": first_available})
+    ))
+}
+
+// Sent when an instrument's channel closes under an active subscriber
+pub(crate) const RECONNECT_FRAME: &[u8] = b"event: reconnect\ndata: {\"reason\":\"channel_closed\"}\n\n";
+
+// Tag a buffered data frame as a snapshot replayed on connect
+pub(crate) fn snapshot_frame(frame: &[u8]) -> Bytes {
+    let mut tagged = b"event: snapshot\n".to_vec();
+    tagged.extend_from_slice(frame);
+    Bytes::from(tagged)
+}
+
+// Compressed form of a connect-time snapshot, for clients that opt in with
+// `?compress_snapshot=true`. The frame is `event: snapshot_gz` whose data line is the
+// standard (padded) base64 of the gzipped JSON payload. To decode: base64-decode the data
+// line, gunzip the bytes, then JSON-parse the result, e.g. in a browser:
+//   new Response(new Blob([Uint8Array.from(atob(e.data), c => c.charCodeAt(0))])
+//       .stream().pipeThrough(new DecompressionStream("gzip"))).json()
+// Live frames that follow are never compressed.
+pub(crate) fn compressed_snapshot_frame(frame: &[u8]) -> Bytes {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let (_, data) = split_frame(frame);
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    let gzipped = encoder
+        .write_all(data.unwrap_or("").as_bytes())
+        .and_then(|_| encoder.finish());
+
+    match gzipped {
+        Ok(gzipped) => Bytes::from(format!("event: snapshot_gz\ndata: {}\n\n", BASE64.encode(gzipped))),
+        Err(_) => snapshot_frame(frame), // in-memory gzip doesn't fail in practice
+    }
+}
+
+// Random delay in [0, max_ms] used to spread out reconnecting clients
+pub(crate) fn connect_jitter(max_ms: u64) -> std::time::Duration {
+    if max_ms == 0 {
+        return std::time::Duration::ZERO;
+    }
+
+    // uuid v4 is backed by the OS RNG, which is plenty for jitter
+    let random = uuid::Uuid::new_v4().as_u128();
+    std::time::Duration::from_millis((random % (max_ms as u128 + 1)) as u64)
+}
+
+// This instance's slice of the instrument universe when it is one of `count` shards.
+// Instruments are assigned by FNV-1a hash of their name, so every instance agrees.
+#[derive(Debug, Clone, Copy)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    pub fn new(index: usize, count: usize) -> Result<Self, String> {
+        if count == 0 || index >= count {
+            return Err(format!("shard_index {} is out of range for shard_count {}", index, count));
+        }
+        Ok(Shard { index, count })
+    }
+
+    pub fn shard_of(&self, instrument: &str) -> usize {
+        let hash = instrument.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        (hash % self.count.max(1) as u64) as usize
+    }
+
+    pub fn owns(&self, instrument: &str) -> bool {
+        self.count <= 1 || self.shard_of(instrument) == self.index
+    }
+}
+
+// 421 for an instrument served by another shard; with one sse_base_urls entry per shard
+// the owning instance is included as a hint
+fn misdirected(app_state: &AppState, instrument: &str) -> HttpResponse {
+    let shard = app_state.shard.shard_of(instrument);
+    println!("Warning: Instrument {} belongs to shard {}, not {}", instrument, shard, app_state.shard.index);
+    let mut body = serde_json::json!({
+        "error": "misdirected_request",
+        "instrument": instrument,
+        "shard": shard
+    });
+    if app_state.sse_base_urls.len() == app_state.shard.count {
+        body["sse_base_url"] = serde_json::json!(app_state.sse_base_urls[shard]);
+    }
+    HttpResponse::build(actix_web::http::StatusCode::MISDIRECTED_REQUEST).json(body)
+}
+
+// Return a 404-like response for unknown instruments
+fn instrument_not_found(instrument: &str) -> HttpResponse {
+    println!("Warning: Unknown instrument requested: {}", instrument);
+    HttpResponse::NotFound()
+        .content_type("text/plain")
+        .body(format!("Instrument '{}' not found", instrument))
+}
+
+const CSV_HEADER: &str = "timestamp,instrument,price,volume\n";
+
+// Quote a CSV cell when it contains a delimiter, quote or newline
+fn csv_cell(value: Option<&serde_json::Value>) -> String {
+    let text = match value {
+        None | Some(serde_json::Value::Null) => return String::new(),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    };
+
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+// One CSV row (timestamp, instrument, price, volume) per data frame; missing fields are empty cells
+pub(crate) fn csv_row(frame: &[u8]) -> Option<String> {
+    let payload = frame_payload(frame)?;
+    let cells: Vec<String> = ["timestamp", "instrument", "price", "volume"]
+        .iter()
+        .map(|field| csv_cell(payload.get(*field)))
+        .collect();
+    Some(format!("{}\n", cells.join(",")))
+}
+
+// The requesting page's origin: the Origin header, or scheme://host[:port] of the Referer
+fn request_origin(req: &HttpRequest) -> Option<String> {
+    if let Some(origin) = req.headers().get(header::ORIGIN).and_then(|v| v.to_str().ok()) {
+        return Some(origin.trim_end_matches('/').to_string());
+    }
+
+    let referer = req.headers().get(header::REFERER).and_then(|v| v.to_str().ok())?;
+    let (scheme, rest) = referer.split_once("://")?;
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    Some(format!("{}://{}", scheme, host))
+}
+
+// Basic browser-origin gate for SSE (not authentication; the headers are trivially forged
+// outside a browser). An empty allowlist admits everything; otherwise requests without an
+// Origin or Referer are rejected too.
+fn origin_allowed(req: &HttpRequest, allowlist: &[String]) -> bool {
+    if allowlist.is_empty() {
+        return true;
+    }
+
+    match request_origin(req) {
+        Some(origin) => allowlist
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(&origin)),
+        None => false,
+    }
+}
+
+// A receiver on a live channel, plus any frame that raced in while checking it
+pub(crate) type LiveReceiver = (broadcast::Receiver<ChannelFrame>, Option<ChannelFrame>);
+
+// Check a fresh receiver is attached to a live channel. Returns None if every sender is
+// gone, otherwise the receiver plus any frame that raced in while checking.
+pub(crate) fn live_receiver(mut rx: broadcast::Receiver<ChannelFrame>) -> Option<LiveReceiver> {
+    match rx.try_recv() {
+        Ok(msg) => Some((rx, Some(msg))),
+        Err(broadcast::error::TryRecvError::Closed) => None,
+        Err(_) => Some((rx, None)),
+    }
+}
+
+// Dead-connection heuristic. SSE has no client acks, so we watch write backpressure instead:
+// actix only polls a body stream again once the previous chunk has been handed to the
+// socket. A half-open client (gone without a FIN) stops draining the socket buffer, so
+// the body stops being polled. The inner stream runs in its own task feeding the body
+// through a one-frame channel; once handing over a frame takes longer than `threshold`
+// the task ends, releasing the inner stream's receiver, buffers and connection slot
+// without waiting for the body to be polled again. A zero threshold disables the check.
+pub(crate) fn end_stalled_stream<S>(
+    inner: S,
+    threshold: std::time::Duration,
+    feed_metrics: Arc<FeedMetrics>,
+) -> impl Stream<Item = Result<Bytes, Error>>
+where
+    S: Stream<Item = Result<Bytes, Error>> + 'static,
+{
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    actix_web::rt::spawn(async move {
+        futures::pin_mut!(inner);
+        loop {
+            // A dropped body (client gone) ends the task even while the inner stream is quiet
+            let item = tokio::select! {
+                item = inner.next() => item,
+                _ = tx.closed() => break,
+            };
+            let item = match item {
+                Some(item) => item,
+                None => break,
+            };
+            if threshold.is_zero() {
+                if tx.send(item).await.is_err() {
+                    break;
+                }
+                continue;
+            }
+            match tx.send_timeout(item, threshold).await {
+                Ok(()) => {}
+                Err(tokio::sync::mpsc::error::SendTimeoutError::Timeout(_)) => {
+                    feed_metrics.dead_connections.fetch_add(1, Ordering::Relaxed);
+                    println!("Warning: Closing stream after a write stalled for over {:?}", threshold);
+                    break;
+                }
+                Err(tokio::sync::mpsc::error::SendTimeoutError::Closed(_)) => break,
+            }
+        }
+    });
+
+    stream! {
+        while let Some(item) = rx.recv().await {
+            yield item;
+        }
+    }
+}
+
+// Why an instrument stream ended, reported in the final `event: closing` frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    Shutdown, // the server is shutting down
+    Lifetime, // max_stream_lifetime_secs reached
+    Disabled, // the instrument was removed from the universe
+    Drained,  // the channel was retired (e.g. replaced on reload)
+    Lagged,   // the client fell behind and on_lag is `disconnect`
+    Idle,     // nothing was written for connection_idle_timeout_secs
+}
+
+// What an instrument stream does when its client falls behind the channel:
+// - `warn` (default): send `event: warn` with the number of skipped frames and carry on
+// - `disconnect`: end the stream, so the client reconnects and starts fresh
+// - `resync`: send the newest buffered frame as `event: snapshot` and skip the stale
+//   frames still queued; falls back to `warn` when nothing is buffered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LagPolicy {
+    #[default]
+    Warn,
+    Disconnect,
+    Resync,
+}
+
+// How instrument streams end; see CloseReason
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CloseOptions {
+    pub emit_closing_frame: bool,
+    pub max_lifetime: Option<std::time::Duration>,
+    pub on_lag: LagPolicy,
+    pub idle_timeout: Option<std::time::Duration>, // close after this long without a successful write
+}
+
+pub(crate) fn closing_frame(reason: CloseReason, frames_sent: u64) -> Bytes {
+    let payload = serde_json::json!({"reason": reason, "frames_sent": frames_sent});
+    Bytes::from(format!("event: closing\ndata: {}\n\n", payload))
+}
+
+// Broadcast to every channel when the server starts shutting down, so clients can
+// reconnect to another instance. It carries an empty data line because EventSource
+// doesn't dispatch events without one.
+pub(crate) const SHUTDOWN_FRAME: &[u8] = b"event: shutdown\ndata: {}\n\n";
+
+// Resolves once shutdown is signalled; never if the signalling side is gone
+async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    loop {
+        if *shutdown.borrow() {
+            return;
+        }
+        if shutdown.changed().await.is_err() {
+            futures::future::pending::<()>().await;
+        }
+    }
+}
+
+// Route for stream endpoints: GET opens the stream, HEAD only reports its headers
+pub fn stream_route() -> actix_web::Route {
+    web::route().guard(guard::Any(guard::Get()).or(guard::Head()))
+}
+
+// Health checkers HEAD stream endpoints; answer with the stream's headers and no body
+// instead of opening a response that never ends
+fn head_response(req: &HttpRequest, content_type: &str) -> Option<HttpResponse> {
+    if req.method() != Method::HEAD {
+        return None;
+    }
+
+    Some(
+        HttpResponse::Ok()
+            .insert_header(("Content-Type", content_type.to_string()))
+            .insert_header(("Cache-Control", "no-cache"))
+            .finish(),
+    )
+}
+
+// Whether the request's Accept header (if any) allows an event stream response
+fn accepts_event_stream(req: &HttpRequest) -> bool {
+    let accept = match req.headers().get(header::ACCEPT).and_then(|v| v.to_str().ok()) {
+        Some(accept) => accept,
+        None => return true,
+    };
+
+    accept.split(',').any(|media_range| {
+        let media_type = media_range.split(';').next().unwrap_or("").trim();
+        media_type.eq_ignore_ascii_case("text/event-stream")
+            || media_type == "text/*"
+            || media_type == "*/*"
+    })
+}
 
 pub async fn sse_handler(
+    req: HttpRequest,
     path: web::Path<String>,
+    query: web::Query<SseQuery>,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, Error> {
-    let instrument = path.as_ref();
-    println!("SSE connection established for instrument: {}", instrument);
+    let instrument = app_state.resolve_instrument(path.as_str());
+    let query = query.into_inner();
+
+    if req.method() == Method::HEAD && app_state.instrument_sender(instrument).is_none() {
+        return Ok(instrument_not_found(instrument));
+    }
+    if !app_state.shard.owns(instrument) {
+        return Ok(misdirected(&app_state, instrument));
+    }
+    if let Some(resp) = head_response(&req, query.format.content_type()) {
+        return Ok(resp);
+    }
+
+    if !origin_allowed(&req, &app_state.sse_referer_allowlist) {
+        println!("Warning: Rejecting SSE request for {} from disallowed origin", instrument);
+        return Ok(HttpResponse::Forbidden().json(serde_json::json!({
+            "error": "origin_not_allowed"
+        })));
+    }
+
+    // Clients that can't parse an event stream get a hint instead of bytes they'll choke on
+    let format = query.format;
+    if format == StreamFormat::Json && !accepts_event_stream(&req) {
+        println!("Warning: Rejecting SSE request for {} with incompatible Accept header", instrument);
+        return Ok(HttpResponse::NotAcceptable().json(serde_json::json!({
+            "error": "not_acceptable",
+            "hint": "This endpoint streams text/event-stream; send 'Accept: text/event-stream' or omit the Accept header"
+        })));
+    }
+
+    if app_state.instrument_sender(instrument).is_none() {
+        return Ok(instrument_not_found(instrument));
+    }
+
+    // Resolve the requested display currency against the cached FX rates
+    let fx = match query.currency {
+        Some(currency) => {
+            let currency = currency.to_ascii_uppercase();
+            let rate = app_state.fx_rates.read().unwrap().get(&currency).copied();
+            match rate {
+                Some(rate) => Some((currency, rate)),
+                None => {
+                    return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                        "error": "unknown_currency",
+                        "currency": currency
+                    })));
+                }
+            }
+        }
+        None => None,
+    };
+    let sample_every = match query.sample.as_deref() {
+        Some(sample) => match parse_sample(sample) {
+            Some(every) => every,
+            None => {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": "invalid_sample",
+                    "sample": sample,
+                    "hint": "Use sample=1/N with N a positive integer"
+                })));
+            }
+        },
+        None => 1,
+    };
+    let ui_hints = if query.include_ui_hints {
+        app_state
+            .instrument_details
+            .read()
+            .unwrap()
+            .get(instrument)
+            .filter(|details| !details.tags.is_empty())
+            .map(|details| serde_json::json!(details.tags))
+    } else {
+        None
+    };
+    let mut pipeline = FramePipeline {
+        fx,
+        fx_rates: app_state.fx_rates.clone(),
+        diff: query.diff,
+        ui_hints,
+        sample_every,
+        fields: query.fields.as_deref().and_then(parse_fields),
+        // The CRC covers JSON text, which the other formats re-encode
+        checksum: query.checksum && format == StreamFormat::Json,
+        ..Default::default()
+    };
+
+    // Under load, protect open streams by refusing new ones; only valid requests get here
+    let connection = match app_state.load_shedder.try_admit() {
+        Some(connection) => connection,
+        None => return Ok(overloaded()),
+    };
 
-    // Look up the instrument-specific broadcast channel
-    let tx = match app_state.instrument_tx.get(instrument) {
-        Some(tx) => tx.clone(),
+    // Smooth reconnection storms before taking a subscription
+    if app_state.connect_jitter_ms > 0 {
+        tokio::time::sleep(connect_jitter(app_state.connect_jitter_ms)).await;
+    }
+
+    // Look up the instrument-specific broadcast channel after the jitter, so a channel
+    // replaced meanwhile isn't subscribed to
+    let tx = match app_state.instrument_sender(instrument) {
+        Some(tx) => tx,
+        None => return Ok(instrument_not_found(instrument)),
+    };
+
+    let conflate_interval = query
+        .conflate_ms
+        .filter(|ms| *ms > 0)
+        .map(std::time::Duration::from_millis);
+
+    let reconnect_on_close = app_state.reconnect_on_channel_close;
+    let compress_snapshot = query.compress_snapshot;
+    let close_options = app_state.close_options;
+    let heartbeat_interval = app_state.heartbeat_interval;
+    let heartbeat_health = HeartbeatHealth::from_state(&app_state);
+    let feed_metrics = app_state.feed_metrics.clone();
+    let mut shutdown = app_state.shutdown.clone();
+    let instrument_details = app_state.instrument_details.clone();
+    let instrument_name = instrument.to_string();
+
+    // Subscribe to the instrument-specific channel; a sender retired mid-reload would
+    // otherwise hand out a receiver that ends the stream straight away
+    let (mut rx, first) = match live_receiver(tx.subscribe()) {
+        Some(live) => live,
         None => {
-            println!("Warning: Unknown instrument requested: {}", instrument);
-            // Return a 404-like response for unknown instruments
-            return Ok(HttpResponse::NotFound()
-                .content_type("text/plain")
-                .body(format!("Instrument '{}' not found", instrument)));
+            app_state.feed_metrics.subscribe_failed.fetch_add(1, Ordering::Relaxed);
+            println!("Warning: Channel for {} closed at subscribe time", instrument);
+            return Ok(HttpResponse::ServiceUnavailable()
+                .insert_header(("Retry-After", "1"))
+                .json(serde_json::json!({
+                    "error": "channel_unavailable",
+                    "hint": "The instrument channel is being replaced; retry shortly"
+                })));
         }
     };
+    // Only a request that gets a stream counts as a connection
+    let connection_log = app_state.connection_log.connected(&[instrument.to_string()]);
 
-    // Subscribe to the instrument-specific channel
-    let mut rx = tx.subscribe();
+    // A reconnecting client resumes after its Last-Event-ID instead of getting a snapshot
+    let last_event_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    // Replay the most recent frames so charts render immediately; subscribing first
+    // means none fall in the gap, and live frames already replayed are skipped by seq
+    let mut snapshot = Vec::new();
+    let mut resume = None;
+    match last_event_id {
+        Some(last_id) => resume = Some(app_state.replay.since(instrument, last_id)),
+        None => {
+            let snapshot_count = app_state.snapshot_frame_count.min(app_state.replay.capacity());
+            snapshot = app_state.replay.last(instrument, snapshot_count);
+            if let Some(session) = &app_state.replay_session {
+                snapshot = session.filter(snapshot, chrono::Utc::now());
+            }
+        }
+    }
+    let replay = app_state.replay.clone();
 
     // Stream the instrument-specific messages
     let stream = stream! {
+        let _connection = connection;
+        let _connection_log = connection_log;
+        let mut frames_sent: u64 = 0;
+        let mut shutdown_sent = false;
+        let mut replayed_through = 0;
+        for buffered in snapshot {
+            replayed_through = buffered.seq;
+            if let Some(frame) = pipeline.apply(&buffered.frame) {
+                let tagged = if compress_snapshot {
+                    compressed_snapshot_frame(&frame)
+                } else {
+                    snapshot_frame(&frame)
+                };
+                frames_sent += 1;
+                yield Ok::<Bytes, Error>(format.encode(&tagged));
+            }
+        }
+        if let Some(resume) = resume {
+            if let (Some(first_available), Some(last_id)) = (resume.gap, last_event_id) {
+                yield Ok::<Bytes, Error>(format.encode(&gap_frame(last_id, first_available)));
+            }
+            for buffered in resume.frames {
+                if let Some(frame) = pipeline.apply(&buffered.frame) {
+                    frames_sent += 1;
+                    yield Ok::<Bytes, Error>(format.encode(&with_event_id(&frame, buffered.seq)));
+                }
+            }
+            replayed_through = resume.through;
+        }
+        if let Some(msg) = first {
+            if msg.seq.is_none_or(|seq| seq > replayed_through) {
+                if let Some(frame) = live_frame(&mut pipeline, &msg, msg.seq) {
+                    frames_sent += 1;
+                    yield Ok::<Bytes, Error>(format.encode(&frame));
+                }
+            }
+        }
+
+        let lifetime = async {
+            match close_options.max_lifetime {
+                Some(max_lifetime) => tokio::time::sleep(max_lifetime).await,
+                None => futures::future::pending::<()>().await,
+            }
+        };
+        tokio::pin!(lifetime);
+
+        // Restarted on every data frame, so heartbeats and warnings don't keep a connection
+        // the client gets nothing from open. The stream runs in end_stalled_stream's task,
+        // so the timer fires without waiting for the body to be polled.
+        let idle = tokio::time::sleep(close_options.idle_timeout.unwrap_or(std::time::Duration::MAX));
+        tokio::pin!(idle);
+        let mut heartbeat = heartbeat_ticker(heartbeat_interval);
+        // With conflation, data frames wait here and only the newest is sent on each tick
+        let mut conflate = heartbeat_ticker(conflate_interval);
+        let mut conflated: Option<ChannelFrame> = None;
+
+        let reason = loop {
+            let mut conflate_due = false;
+            let received = tokio::select! {
+                received = rx.recv() => Some(received),
+                _ = heartbeat.tick(), if heartbeat_interval.is_some() => None,
+                _ = conflate.tick(), if conflate_interval.is_some() => {
+                    conflate_due = true;
+                    None
+                }
+                _ = &mut lifetime => break CloseReason::Lifetime,
+                _ = &mut idle, if close_options.idle_timeout.is_some() => break CloseReason::Idle,
+                _ = shutdown_requested(&mut shutdown) => break CloseReason::Shutdown,
+            };
+            let received = match received {
+                Some(received) => received,
+                None if conflate_due => {
+                    if let Some(frame) = conflated.take().and_then(|msg| live_frame(&mut pipeline, &msg, msg.seq)) {
+                        frames_sent += 1;
+                        yield Ok::<Bytes, Error>(format.encode(&frame));
+                        if let Some(idle_timeout) = close_options.idle_timeout {
+                            idle.as_mut().reset(tokio::time::Instant::now() + idle_timeout);
+                        }
+                    }
+                    continue;
+                }
+                None => {
+                    yield Ok::<Bytes, Error>(format.encode(&heartbeat_frame(heartbeat_health.as_ref())));
+                    continue;
+                }
+            };
+
+            match received {
+                Ok(msg) => {
+                    shutdown_sent |= msg.as_ref() == SHUTDOWN_FRAME;
+                    if msg.seq.is_some_and(|seq| seq <= replayed_through) {
+                        continue; // already replayed
+                    }
+                    if conflate_interval.is_some() && msg.starts_with(b"data: ") {
+                        conflated = Some(msg);
+                        continue;
+                    }
+                    if let Some(frame) = live_frame(&mut pipeline, &msg, msg.seq) {
+                        frames_sent += 1;
+                        yield Ok::<Bytes, Error>(format.encode(&frame));
+                        if let Some(idle_timeout) = close_options.idle_timeout.filter(|_| msg.starts_with(b"data: ")) {
+                            idle.as_mut().reset(tokio::time::Instant::now() + idle_timeout);
+                        }
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    feed_metrics.record_lag(&instrument_name);
+                    if close_options.on_lag == LagPolicy::Disconnect {
+                        break CloseReason::Lagged;
+                    }
+                    if close_options.on_lag == LagPolicy::Resync {
+                        if let Some(latest) = replay.last(&instrument_name, 1).pop() {
+                            // Queued frames up to the newest one are stale now
+                            replayed_through = replayed_through.max(latest.seq);
+                            if let Some(frame) = pipeline.apply(&latest.frame) {
+                                frames_sent += 1;
+                                yield Ok(format.encode(&snapshot_frame(&frame)));
+                                if let Some(idle_timeout) = close_options.idle_timeout {
+                                    idle.as_mut().reset(tokio::time::Instant::now() + idle_timeout);
+                                }
+                            }
+                            continue;
+                        }
+                    }
+                    // Tell the client it fell behind and carry on
+                    let warn = format!("event: warn\ndata: {{\"lagged\": {}}}\n\n", skipped);
+                    yield Ok(format.encode(warn.as_bytes()));
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    // The channel was retired (reload or shutdown) while we were subscribed;
+                    // ask the client to reconnect to its replacement instead of just ending
+                    if let Some(frame) = conflated.take().and_then(|msg| live_frame(&mut pipeline, &msg, msg.seq)) {
+                        frames_sent += 1;
+                        yield Ok(format.encode(&frame));
+                    }
+                    if reconnect_on_close {
+                        yield Ok(format.encode(RECONNECT_FRAME));
+                    }
+                    break if instrument_details.read().unwrap().contains_key(&instrument_name) {
+                        CloseReason::Drained
+                    } else {
+                        CloseReason::Disabled
+                    };
+                }
+            }
+        };
+
+        // A frame held back by conflation is still the newest state; don't lose it
+        if let Some(frame) = conflated.take().and_then(|msg| live_frame(&mut pipeline, &msg, msg.seq)) {
+            frames_sent += 1;
+            yield Ok(format.encode(&frame));
+        }
+        // The shutdown signal can win the race against the broadcast shutdown frame
+        if reason == CloseReason::Shutdown && !shutdown_sent {
+            yield Ok(format.encode(SHUTDOWN_FRAME));
+        }
+        // An idle close always says why, so a silent client can tell it from a network drop
+        if close_options.emit_closing_frame || reason == CloseReason::Idle {
+            yield Ok(format.encode(&closing_frame(reason, frames_sent)));
+        }
+    };
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header(("Content-Type", format.content_type()))
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("Connection", "keep-alive"));
+    for link in preload_links(&app_state.sse_preload_links, instrument) {
+        response.append_header((header::LINK, link));
+    }
+    Ok(response.streaming(end_stalled_stream(stream, app_state.dead_connection_threshold, app_state.feed_metrics.clone())))
+}
+
+// Interval between frames on /sse/{instrument}/diag
+const DIAG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Live delivery view of one instrument for troubleshooting: current subscribers and
+// buffered frames, plus lag events and messages per second since the previous frame.
+// The first frame is sent straight away.
+pub async fn sse_diag_handler(
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let instrument = app_state.resolve_instrument(path.as_str()).to_string();
+    let tx = match app_state.instrument_sender(&instrument) {
+        Some(tx) => tx,
+        None => return Ok(instrument_not_found(&instrument)),
+    };
+    let replay = app_state.replay.clone();
+    let feed_metrics = app_state.feed_metrics.clone();
+    let mut shutdown = app_state.shutdown.clone();
+
+    let stream = stream! {
+        let mut interval = tokio::time::interval(DIAG_INTERVAL);
+        let mut last_lag_events = feed_metrics.instrument_lag_events(&instrument);
+        let mut last_messages = feed_metrics.instrument_messages(&instrument);
+        let mut last_at = std::time::Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown_requested(&mut shutdown) => break,
+            }
+
+            let lag_events = feed_metrics.instrument_lag_events(&instrument);
+            let messages = feed_metrics.instrument_messages(&instrument);
+            let elapsed = last_at.elapsed().as_secs_f64();
+            let messages_per_sec = if elapsed > 0.0 {
+                (messages - last_messages) as f64 / elapsed
+            } else {
+                0.0
+            };
+            let diag = serde_json::json!({
+                "instrument": instrument,
+                "subscribers": tx.receiver_count(),
+                "buffered_frames": replay.buffered(&instrument),
+                "lag_events": lag_events - last_lag_events,
+                "messages_per_sec": (messages_per_sec * 100.0).round() / 100.0
+            });
+            (last_lag_events, last_messages, last_at) = (lag_events, messages, std::time::Instant::now());
+            yield Ok::<Bytes, Error>(Bytes::from(format!("event: diag\ndata: {}\n\n", diag)));
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Content-Type", "text/event-stream"))
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("Connection", "keep-alive"))
+        .streaming(stream))
+}
+
+// `Link: <url>; rel=preload` values advertising companion resources of an instrument
+// stream; `{instrument}` in a configured URL is replaced with the instrument name
+pub(crate) fn preload_links(templates: &[String], instrument: &str) -> Vec<String> {
+    templates
+        .iter()
+        .map(|template| format!("<{}>; rel=preload", template.replace("{instrument}", instrument)))
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MultiQuery {
+    instruments: String, // comma-separated instrument names or aliases
+}
+
+// Resolve, de-duplicate (keeping first occurrence order) and cap a multi-subscription
+// list, returned as (known, unknown) instruments. Fails when none is known.
+pub(crate) fn parse_multi_instruments(
+    app_state: &AppState,
+    requested: &str,
+) -> Result<(Vec<String>, Vec<String>), HttpResponse> {
+    let mut instruments: Vec<String> = Vec::new();
+    let mut unknown: Vec<String> = Vec::new();
+    for name in requested.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+        let instrument = app_state.resolve_instrument(name);
+        let list = match app_state.instrument_sender(instrument) {
+            Some(_) => &mut instruments,
+            None => &mut unknown,
+        };
+        if !list.iter().any(|seen| seen == instrument) {
+            list.push(instrument.to_string());
+        }
+    }
+
+    if instruments.is_empty() {
+        if let Some(instrument) = unknown.first() {
+            return Err(instrument_not_found(instrument));
+        }
+    }
+    if instruments.is_empty() || instruments.len() > app_state.max_multi_instruments {
+        return Err(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "invalid_instrument_count",
+            "requested": instruments.len(),
+            "max": app_state.max_multi_instruments
+        })));
+    }
+
+    Ok((instruments, unknown))
+}
+
+// Name a data frame's event after its instrument, for clients routing one merged stream;
+// frames that already carry an event name keep it
+pub(crate) fn tag_instrument_event(frame: &Bytes, instrument: &str) -> Bytes {
+    if !frame.starts_with(b"data: ") {
+        return frame.clone();
+    }
+    let mut tagged = format!("event: {}\n", instrument).into_bytes();
+    tagged.extend_from_slice(frame);
+    Bytes::from(tagged)
+}
+
+// Several instruments on one connection, frames interleaved as they arrive. Each
+// instrument holds its own broadcast receiver (and lag window) for the life of the
+// connection, so the list is capped at max_multi_instruments. Any unknown instrument
+// fails the request.
+pub async fn multi_sse_handler(
+    req: HttpRequest,
+    query: web::Query<MultiQuery>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let instruments = match parse_multi_instruments(&app_state, &query.instruments) {
+        Ok((_, unknown)) if !unknown.is_empty() => return Ok(instrument_not_found(&unknown[0])),
+        Ok((instruments, _)) => instruments,
+        Err(resp) => return Ok(resp),
+    };
+    merged_sse_response(&req, &app_state, instruments, Vec::new(), false)
+}
+
+// `/sse?instruments=AAPL,GOOGL`: like /sse/multi, but each data frame is sent as
+// `event: <instrument>` so clients can route it, and unknown instruments are skipped.
+// They are listed in a leading `event: error` frame with `instrument_not_found`.
+pub async fn tagged_multi_sse_handler(
+    req: HttpRequest,
+    query: web::Query<MultiQuery>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let (instruments, unknown) = match parse_multi_instruments(&app_state, &query.instruments) {
+        Ok(parsed) => parsed,
+        Err(resp) => return Ok(resp),
+    };
+    if !unknown.is_empty() {
+        println!("Warning: Skipping unknown instruments in multi subscription: {}", unknown.join(","));
+    }
+    merged_sse_response(&req, &app_state, instruments, unknown, true)
+}
+
+// One stream interleaving the instruments' channels; `unknown` instruments are reported
+// in a first `event: error` frame
+fn merged_sse_response(
+    req: &HttpRequest,
+    app_state: &AppState,
+    instruments: Vec<String>,
+    unknown: Vec<String>,
+    tag_events: bool,
+) -> Result<HttpResponse, Error> {
+    if let Some(resp) = head_response(req, "text/event-stream") {
+        return Ok(resp);
+    }
+
+    let connection = match app_state.load_shedder.try_admit() {
+        Some(connection) => connection,
+        None => return Ok(overloaded()),
+    };
+
+    let connection_log = app_state.connection_log.connected(&instruments);
+
+    let streams = instruments.into_iter().map(|instrument| {
+        let mut rx = app_state.instrument_sender(&instrument).expect("checked by parse_multi_instruments").subscribe();
+        Box::pin(stream! {
+            loop {
+                match rx.recv().await {
+                    Ok(msg) if tag_events => yield Ok::<Bytes, Error>(tag_instrument_event(&msg, &instrument)),
+                    Ok(msg) => yield Ok((*msg).clone()),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        let warn = format!("event: warn\ndata: {{\"lagged\": {}}}\n\n", skipped);
+                        yield Ok(Bytes::from(warn));
+                    }
+                    Err(_) => break, // channel closed
+                }
+            }
+        })
+    });
+    let mut merged = futures::stream::select_all(streams);
+
+    let stream = stream! {
+        let _connection = connection;
+        let _connection_log = connection_log;
+        if !unknown.is_empty() {
+            let error = serde_json::json!({"error": "instrument_not_found", "instruments": unknown});
+            yield Ok(Bytes::from(format!("event: error\ndata: {}\n\n", error)));
+        }
+        while let Some(frame) = merged.next().await {
+            yield frame;
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Content-Type", "text/event-stream"))
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("Connection", "keep-alive"))
+        .streaming(end_stalled_stream(stream, app_state.dead_connection_threshold, app_state.feed_metrics.clone())))
+}
+
+// Continuously appending CSV of an instrument's ticks for spreadsheet-style consumers
+pub async fn csv_stream_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let instrument = app_state.resolve_instrument(path.as_str());
+
+    let tx = match app_state.instrument_sender(instrument) {
+        Some(tx) => tx,
+        None => return Ok(instrument_not_found(instrument)),
+    };
+    if let Some(resp) = head_response(&req, "text/csv; charset=utf-8") {
+        return Ok(resp);
+    }
+
+    let connection = match app_state.load_shedder.try_admit() {
+        Some(connection) => connection,
+        None => return Ok(overloaded()),
+    };
+
+    println!("CSV stream established for instrument: {}", instrument);
+    let mut rx = tx.subscribe();
+
+    let stream = stream! {
+        let _connection = connection;
+        yield Ok::<Bytes, Error>(Bytes::from_static(CSV_HEADER.as_bytes()));
+
+        loop {
+            match rx.recv().await {
+                Ok(msg) => {
+                    if let Some(row) = csv_row(&msg) {
+                        yield Ok(Bytes::from(row));
+                    }
+                }
+                // CSV has no side channel for warnings; skipped ticks are simply absent
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(_) => break, // channel closed
+            }
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Content-Type", "text/csv; charset=utf-8"))
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(end_stalled_stream(stream, app_state.dead_connection_threshold, app_state.feed_metrics.clone())))
+}
+
+// Per-underlying position/PnL totals: a full snapshot on connect, then a frame per change
+pub async fn pnl_by_underlying_sse_handler(
+    req: HttpRequest,
+    query: web::Query<SnapshotQuery>,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    if let Some(resp) = head_response(&req, "text/event-stream") {
+        return Ok(resp);
+    }
+
+    let connection = match app_state.load_shedder.try_admit() {
+        Some(connection) => connection,
+        None => return Ok(overloaded()),
+    };
+
+    println!("SSE connection established for PnL by underlying");
+
+    let mut rx = app_state.pnl_by_underlying.subscribe();
+    let snapshot = app_state.pnl_by_underlying.latest();
+    let snapshot = if query.compress_snapshot {
+        compressed_snapshot_frame(&snapshot)
+    } else {
+        (*snapshot).clone()
+    };
+
+    let stream = stream! {
+        let _connection = connection;
+        yield Ok::<Bytes, Error>(snapshot);
+
         loop {
             match rx.recv().await {
                 Ok(msg) => {
-                    // Clone is cheap: Arc<Bytes>
                     yield Ok::<Bytes, Error>((*msg).clone());
                 }
                 Err(broadcast::error::RecvError::Lagged(skipped)) => {
-                    // Tell the client it fell behind; you can also `break` to drop
                     let warn = format!("event: warn\ndata: {{\"lagged\": {}}}\n\n", skipped);
                     yield Ok(Bytes::from(warn));
                 }
@@ -48,21 +1328,110 @@ pub async fn sse_handler(
         .insert_header(("Content-Type", "text/event-stream"))
         .insert_header(("Cache-Control", "no-cache"))
         .insert_header(("Connection", "keep-alive"))
-        .streaming(stream))
+        .streaming(end_stalled_stream(stream, app_state.dead_connection_threshold, app_state.feed_metrics.clone())))
+}
+
+// `/sse/all`: every instrument's frames interleaved on one connection, each data frame
+// sent as `event: <instrument>`. Reads the firehose channel the pump fills when
+// enable_firehose is on, rather than one receiver per instrument.
+pub async fn firehose_sse_handler(
+    req: HttpRequest,
+    app_state: web::Data<AppState>,
+) -> Result<HttpResponse, Error> {
+    let firehose_tx = match &app_state.firehose_tx {
+        Some(firehose_tx) => firehose_tx,
+        None => {
+            return Ok(HttpResponse::NotFound().json(serde_json::json!({
+                "error": "firehose_disabled",
+                "hint": "Set enable_firehose in the config to stream all instruments"
+            })));
+        }
+    };
+    if let Some(resp) = head_response(&req, "text/event-stream") {
+        return Ok(resp);
+    }
+
+    let connection = match app_state.load_shedder.try_admit() {
+        Some(connection) => connection,
+        None => return Ok(overloaded()),
+    };
+
+    println!("SSE connection established for all instruments");
+
+    let mut rx = firehose_tx.subscribe();
+    let heartbeat_interval = app_state.heartbeat_interval;
+    let heartbeat_health = HeartbeatHealth::from_state(&app_state);
+
+    let stream = stream! {
+        let _connection = connection;
+        let mut heartbeat = heartbeat_ticker(heartbeat_interval);
+        loop {
+            let received = tokio::select! {
+                received = rx.recv() => Some(received),
+                _ = heartbeat.tick(), if heartbeat_interval.is_some() => None,
+            };
+            let received = match received {
+                Some(received) => received,
+                None => {
+                    yield Ok::<Bytes, Error>(heartbeat_frame(heartbeat_health.as_ref()));
+                    continue;
+                }
+            };
+            match received {
+                Ok(msg) => yield Ok::<Bytes, Error>((*msg).clone()),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    let warn = format!("event: warn\ndata: {{\"lagged\": {}}}\n\n", skipped);
+                    yield Ok(Bytes::from(warn));
+                }
+                Err(_) => break, // channel closed
+            }
+        }
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Content-Type", "text/event-stream"))
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("Connection", "keep-alive"))
+        .streaming(end_stalled_stream(stream, app_state.dead_connection_threshold, app_state.feed_metrics.clone())))
 }
 
 pub async fn pnl_sse_handler(
+    req: HttpRequest,
     app_state: web::Data<AppState>,
 ) -> Result<HttpResponse, Error> {
+    if let Some(resp) = head_response(&req, "text/event-stream") {
+        return Ok(resp);
+    }
+
+    let connection = match app_state.load_shedder.try_admit() {
+        Some(connection) => connection,
+        None => return Ok(overloaded()),
+    };
+
     println!("SSE connection established for position/PnL updates");
 
     // Subscribe to the single position/PnL channel
     let mut rx = app_state.pnl_tx.subscribe();
+    let heartbeat_interval = app_state.heartbeat_interval;
+    let heartbeat_health = HeartbeatHealth::from_state(&app_state);
 
     // Stream all position and PnL update messages
     let stream = stream! {
+        let _connection = connection;
+        let mut heartbeat = heartbeat_ticker(heartbeat_interval);
         loop {
-            match rx.recv().await {
+            let received = tokio::select! {
+                received = rx.recv() => Some(received),
+                _ = heartbeat.tick(), if heartbeat_interval.is_some() => None,
+            };
+            let received = match received {
+                Some(received) => received,
+                None => {
+                    yield Ok::<Bytes, Error>(heartbeat_frame(heartbeat_health.as_ref()));
+                    continue;
+                }
+            };
+            match received {
                 Ok(msg) => {
                     // Clone is cheap: Arc<Bytes>
                     yield Ok::<Bytes, Error>((*msg).clone());
@@ -81,5 +1450,5 @@ pub async fn pnl_sse_handler(
         .insert_header(("Content-Type", "text/event-stream"))
         .insert_header(("Cache-Control", "no-cache"))
         .insert_header(("Connection", "keep-alive"))
-        .streaming(stream))
+        .streaming(end_stalled_stream(stream, app_state.dead_connection_threshold, app_state.feed_metrics.clone())))
 }
@@ -0,0 +1,90 @@
+use crate::replay::{ChannelFrame, ReplayBuffer};
+use crate::InstrumentChannels;
+use actix_web::web::Bytes;
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// Tracks which instruments have stopped ticking, so `event: stale` is sent once when an
+// instrument goes quiet and `event: fresh` once it ticks again. Instruments that have
+// never ticked are left alone.
+pub struct StalenessTracker {
+    stale_after: chrono::Duration,
+    stale: HashSet<String>,
+}
+
+fn stale_frame(instrument: &str, age_ms: i64) -> Arc<Bytes> {
+    Arc::new(Bytes::from(format!(
+        "event: stale\ndata: {{\"instrument\":{},\"age_ms\":{}}}\n\n",
+        serde_json::json!(instrument),
+        age_ms
+    )))
+}
+
+fn fresh_frame(instrument: &str) -> Arc<Bytes> {
+    Arc::new(Bytes::from(format!(
+        "event: fresh\ndata: {{\"instrument\":{}}}\n\n",
+        serde_json::json!(instrument)
+    )))
+}
+
+impl StalenessTracker {
+    pub fn new(stale_after: Duration) -> Self {
+        StalenessTracker {
+            stale_after: chrono::Duration::from_std(stale_after).unwrap_or(chrono::Duration::MAX),
+            stale: HashSet::new(),
+        }
+    }
+
+    // Compare each instrument's last frame time against the threshold, sending stale/fresh
+    // transitions to its channel. Returns the number of events sent.
+    pub fn sweep(
+        &mut self,
+        replay: &ReplayBuffer,
+        instrument_tx: &HashMap<String, broadcast::Sender<ChannelFrame>>,
+        now: DateTime<Utc>,
+    ) -> usize {
+        let mut sent = 0;
+        for (instrument, tx) in instrument_tx {
+            let last_update = match replay.last(instrument, 1).pop() {
+                Some(buffered) => buffered.received_at,
+                None => continue,
+            };
+
+            let age = now - last_update;
+            let frame = if age > self.stale_after {
+                if !self.stale.insert(instrument.clone()) {
+                    continue;
+                }
+                stale_frame(instrument, age.num_milliseconds())
+            } else {
+                if !self.stale.remove(instrument) {
+                    continue;
+                }
+                fresh_frame(instrument)
+            };
+
+            let _ = tx.send(frame.into()); // ignore if no listeners
+            sent += 1;
+        }
+        sent
+    }
+}
+
+// Background sweep every `interval` for instruments older than `stale_after`
+pub async fn staleness_sweeper(
+    replay: Arc<ReplayBuffer>,
+    instrument_tx: InstrumentChannels,
+    stale_after: Duration,
+    interval: Duration,
+) {
+    let mut tracker = StalenessTracker::new(stale_after);
+    let mut interval = tokio::time::interval(interval.max(Duration::from_millis(10)));
+
+    loop {
+        interval.tick().await;
+        tracker.sweep(&replay, &instrument_tx.read().unwrap(), Utc::now());
+    }
+}
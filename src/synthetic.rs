@@ -0,0 +1,119 @@
+use crate::replay::ChannelFrame;
+use crate::sse::{data_frame, frame_payload};
+use crate::InstrumentChannels;
+use actix_web::web::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SyntheticInstrumentConfig {
+    pub name: String,
+    pub weights: HashMap<String, f64>, // constituent instrument -> weight
+}
+
+// Last known constituent prices for one weighted basket
+pub struct BasketState {
+    weights: HashMap<String, f64>,
+    last_prices: HashMap<String, f64>,
+}
+
+impl BasketState {
+    pub fn new(weights: HashMap<String, f64>) -> Self {
+        BasketState {
+            weights,
+            last_prices: HashMap::new(),
+        }
+    }
+
+    // Record a constituent tick and return the recomputed basket price
+    pub fn on_tick(&mut self, instrument: &str, price: f64) -> Option<f64> {
+        if !self.weights.contains_key(instrument) {
+            return None;
+        }
+
+        self.last_prices.insert(instrument.to_string(), price);
+        self.price()
+    }
+
+    // Weighted sum over each constituent's last known price; None until every constituent has priced
+    pub fn price(&self) -> Option<f64> {
+        self.weights
+            .iter()
+            .map(|(instrument, weight)| self.last_prices.get(instrument).map(|price| price * weight))
+            .sum()
+    }
+}
+
+fn basket_frame(name: &str, price: f64) -> Bytes {
+    data_frame(&serde_json::json!({
+        "type": "basket_update",
+        "instrument": name,
+        "price": price,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+    }))
+}
+
+// Subscribe to a basket's constituent channels and broadcast its recomputed price on
+// a channel of its own (of `capacity`), so it can be served via /sse/{basket} like any
+// other instrument. A constituent channel retired by a reload is followed to its replacement.
+pub fn spawn_basket(
+    config: &SyntheticInstrumentConfig,
+    instrument_tx: &InstrumentChannels,
+    capacity: usize,
+) -> Result<broadcast::Sender<ChannelFrame>, String> {
+    let channels = instrument_tx.read().unwrap();
+    if let Some(missing) = config.weights.keys().find(|name| !channels.contains_key(*name)) {
+        return Err(format!("unknown constituent instrument '{}'", missing));
+    }
+
+    let (basket_tx, _rx) = broadcast::channel::<ChannelFrame>(capacity);
+    let (tick_tx, mut tick_rx) = mpsc::channel::<(String, f64)>(1024);
+
+    // One forwarder per constituent feeds prices into the basket task
+    for constituent in config.weights.keys() {
+        let mut rx = channels[constituent].subscribe();
+        let instrument_tx = instrument_tx.clone();
+        let tick_tx = tick_tx.clone();
+        let constituent = constituent.clone();
+
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(msg) => {
+                        let price = frame_payload(&msg)
+                            .and_then(|payload| payload.get("price").and_then(|v| v.as_f64()));
+                        if let Some(price) = price {
+                            if tick_tx.send((constituent.clone(), price)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        match instrument_tx.read().unwrap().get(&constituent) {
+                            Some(tx) => rx = tx.subscribe(),
+                            None => break,
+                        }
+                    }
+                }
+            }
+        });
+    }
+    drop(tick_tx);
+
+    let name = config.name.clone();
+    let mut state = BasketState::new(config.weights.clone());
+    let tx = basket_tx.clone();
+
+    tokio::spawn(async move {
+        while let Some((constituent, price)) = tick_rx.recv().await {
+            if let Some(basket_price) = state.on_tick(&constituent, price) {
+                let _ = tx.send(Arc::new(basket_frame(&name, basket_price)).into()); // ignore if no listeners
+            }
+        }
+    });
+
+    Ok(basket_tx)
+}
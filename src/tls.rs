@@ -0,0 +1,150 @@
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, SupportedProtocolVersion};
+use std::sync::Arc;
+
+// Protocol versions a TLS listener may negotiate; anything older than 1.2 is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl TlsVersion {
+    // Accepts "1.2"/"1.3", optionally prefixed with "TLS", "TLSv" or "TLS "
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let trimmed = value.trim();
+        let number = ["TLSv", "TLS ", "TLS"]
+            .iter()
+            .find_map(|prefix| {
+                trimmed
+                    .get(..prefix.len())
+                    .filter(|head| head.eq_ignore_ascii_case(prefix))
+                    .map(|_| &trimmed[prefix.len()..])
+            })
+            .unwrap_or(trimmed);
+        match number {
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            "1.0" | "1" | "1.1" => Err(format!("min_tls_version {} is insecure; use 1.2 or 1.3", value)),
+            _ => Err(format!("Invalid min_tls_version {:?}; expected 1.2 or 1.3", value)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TlsVersion::Tls12 => "TLSv1.2",
+            TlsVersion::Tls13 => "TLSv1.3",
+        }
+    }
+
+    fn rustls(self) -> &'static SupportedProtocolVersion {
+        match self {
+            TlsVersion::Tls12 => &rustls::version::TLS12,
+            TlsVersion::Tls13 => &rustls::version::TLS13,
+        }
+    }
+}
+
+// AEAD suites with forward secrecy, by IANA name and the version they belong to. These are
+// also the defaults, strongest first.
+const SECURE_CIPHER_SUITES: [(&str, TlsVersion); 9] = [
+    ("TLS13_AES_256_GCM_SHA384", TlsVersion::Tls13),
+    ("TLS13_AES_128_GCM_SHA256", TlsVersion::Tls13),
+    ("TLS13_CHACHA20_POLY1305_SHA256", TlsVersion::Tls13),
+    ("TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384", TlsVersion::Tls12),
+    ("TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256", TlsVersion::Tls12),
+    ("TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256", TlsVersion::Tls12),
+    ("TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384", TlsVersion::Tls12),
+    ("TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256", TlsVersion::Tls12),
+    ("TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256", TlsVersion::Tls12),
+];
+
+// Validated protocol and cipher policy for a TLS listener
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsPolicy {
+    pub min_version: TlsVersion,
+    pub cipher_suites: Vec<&'static str>,
+}
+
+impl TlsPolicy {
+    // An empty cipher list means every secure suite the minimum version allows. Suites
+    // outside the secure set (CBC, RSA key exchange, ...) are rejected rather than dropped,
+    // as is a list that leaves nothing to negotiate.
+    pub fn new(min_version: TlsVersion, cipher_suites: &[String]) -> Result<Self, String> {
+        let mut selected = Vec::new();
+        for requested in cipher_suites {
+            match SECURE_CIPHER_SUITES.iter().find(|(name, _)| name.eq_ignore_ascii_case(requested)) {
+                Some((name, _)) => selected.push(*name),
+                None => return Err(format!("TLS cipher suite '{}' is not allowed; use an ECDHE AEAD suite", requested)),
+            }
+        }
+        if cipher_suites.is_empty() {
+            selected = SECURE_CIPHER_SUITES.iter().map(|(name, _)| *name).collect();
+        }
+
+        selected.retain(|name| {
+            SECURE_CIPHER_SUITES
+                .iter()
+                .any(|(secure, version)| secure == name && *version >= min_version)
+        });
+        if selected.is_empty() {
+            return Err(format!(
+                "No configured TLS cipher suite can be used with min_tls_version {}",
+                min_version.name()
+            ));
+        }
+
+        Ok(TlsPolicy { min_version, cipher_suites: selected })
+    }
+
+    // rustls server config that negotiates only this policy's versions and suites, the
+    // suites in policy order
+    pub fn server_config(
+        &self,
+        cert_chain: Vec<CertificateDer<'static>>,
+        key: PrivateKeyDer<'static>,
+    ) -> Result<ServerConfig, String> {
+        let mut provider = ring::default_provider();
+        let available = std::mem::take(&mut provider.cipher_suites);
+        provider.cipher_suites = self
+            .cipher_suites
+            .iter()
+            .filter_map(|name| available.iter().find(|suite| format!("{:?}", suite.suite()) == *name).copied())
+            .collect();
+
+        let versions: Vec<&'static SupportedProtocolVersion> = [TlsVersion::Tls12, TlsVersion::Tls13]
+            .into_iter()
+            .filter(|version| *version >= self.min_version)
+            .map(TlsVersion::rustls)
+            .collect();
+
+        ServerConfig::builder_with_provider(Arc::new(provider))
+            .with_protocol_versions(&versions)
+            .map_err(|e| format!("Invalid TLS policy: {}", e))?
+            .with_no_client_auth()
+            .with_single_cert(cert_chain, key)
+            .map_err(|e| format!("Invalid TLS certificate or key: {}", e))
+    }
+}
+
+// Server config for a PEM certificate chain and private key under `policy`
+pub fn load_server_config(policy: &TlsPolicy, cert_path: &str, key_path: &str) -> Result<ServerConfig, String> {
+    let open = |path: &str| {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .map_err(|e| format!("Failed to read TLS file '{}': {}", path, e))
+    };
+
+    let cert_chain = rustls_pemfile::certs(&mut open(cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid TLS certificate '{}': {}", cert_path, e))?;
+    if cert_chain.is_empty() {
+        return Err(format!("No certificate found in '{}'", cert_path));
+    }
+    let key = rustls_pemfile::private_key(&mut open(key_path)?)
+        .map_err(|e| format!("Invalid TLS private key '{}': {}", key_path, e))?
+        .ok_or_else(|| format!("No private key found in '{}'", key_path))?;
+
+    policy.server_config(cert_chain, key)
+}
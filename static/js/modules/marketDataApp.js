@@ -104,6 +104,12 @@ class MarketDataApp {
                 this.eventSource.onmessage = (event) => {
                     this.handleMessage(event);
                 };
+
+                // The last known value arrives as a named `snapshot` event right after
+                // connecting, so the view isn't empty until the next tick
+                this.eventSource.addEventListener('snapshot', (event) => {
+                    this.handleMessage(event);
+                });
             
                 this.eventSource.onerror = (event) => {
                     console.error('EventSource failed:', event);